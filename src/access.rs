@@ -0,0 +1,206 @@
+use std::fmt;
+
+use crate::decoder::{Instruction, Segment};
+
+/// Which way data moves between a memory operand and the CPU.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AccessKind {
+    Load,
+    Store,
+    LoadStore
+}
+
+/// A single memory access made by an instruction.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MemAccess {
+    pub addr: u64,
+    /// Width of the access in bytes.
+    pub size: u16,
+    pub kind: AccessKind
+}
+
+impl fmt::Display for MemAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Load => "load",
+            AccessKind::Store => "store",
+            AccessKind::LoadStore => "load/store"
+        };
+        write!(f, "{} {} @ {:x}", kind, self.size, self.addr)
+    }
+}
+
+// Instructions whose first operand is only written.
+static STORE_DEST: &[&str] = &[
+    "MOV", "MOVZX", "MOVSX", "MOVSXD", "MOVNTI", "MOVBE",
+    "MOVD", "MOVQ", "MOVSS", "MOVSD", "MOVAPS", "MOVAPD", "MOVUPS", "MOVUPD",
+    "MOVDQA", "MOVDQU", "MOVLPS", "MOVHPS", "MOVLPD", "MOVHPD",
+    "MOVNTDQ", "MOVNTPS", "MOVNTPD", "MOVNTQ", "MASKMOVQ", "MASKMOVDQU", "VMASKMOVDQU",
+    "EXTRACTPS", "PEXTRB", "PEXTRW", "PEXTRD", "PEXTRQ",
+    "POP", "STMXCSR", "VSTMXCSR", "FXSAVE", "FXSAVE64", "XSAVE", "XSAVE64",
+    "FST", "FSTP", "FIST", "FISTP", "FISTTP", "FBSTP", "FNSTCW", "FNSTSW", "FNSTENV", "FNSAVE",
+    "SGDT", "SIDT", "STR", "SLDT", "SMSW"
+];
+
+// Instructions whose first operand is only read.
+static LOAD_DEST: &[&str] = &[
+    "CMP", "TEST", "BT", "PUSH", "JMP", "CALL", "JMP FAR", "CALL FAR",
+    "MUL", "IMUL", "DIV", "IDIV",
+    "UCOMISS", "UCOMISD", "COMISS", "COMISD", "PTEST",
+    "LDMXCSR", "VLDMXCSR", "FXRSTOR", "FXRSTOR64", "XRSTOR", "XRSTOR64",
+    "FLD", "FILD", "FBLD", "FLDCW", "FLDENV", "FRSTOR",
+    "FADD", "FIADD", "FSUB", "FISUB", "FSUBR", "FISUBR", "FMUL", "FIMUL",
+    "FDIV", "FIDIV", "FDIVR", "FIDIVR", "FCOM", "FCOMP", "FICOM", "FICOMP",
+    "PREFETCHNTA", "PREFETCHT0", "PREFETCHT1", "PREFETCHT2", "PREFETCHW", "PREFETCH",
+    "CLFLUSH", "CLFLUSHOPT"
+];
+
+// Instructions with a memory operand that isn't actually accessed.
+static NO_ACCESS: &[&str] = &["LEA", "NOP"];
+
+/// Work out how the operand at position `operand` of an instruction with the given
+/// (upper case, unprefixed) mnemonic accesses memory, or None if it doesn't.
+///
+/// Only the first operand is ever written; any later memory operand is a source.
+/// The exceptions are XCHG, which can have its memory operand in either slot, and
+/// the handful of instructions that compute an address without using it.
+pub fn classify(mnemonic: &str, operand: usize) -> Option<AccessKind> {
+    if NO_ACCESS.contains(&mnemonic) {
+        return None;
+    }
+    if mnemonic == "XCHG" {
+        return Some(AccessKind::LoadStore);
+    }
+    if operand > 0 {
+        return Some(AccessKind::Load);
+    }
+    if STORE_DEST.contains(&mnemonic) || mnemonic.starts_with("SET") || mnemonic.starts_with("VMOV") {
+        return Some(AccessKind::Store);
+    }
+    if LOAD_DEST.contains(&mnemonic) {
+        return Some(AccessKind::Load);
+    }
+    Some(AccessKind::LoadStore)
+}
+
+/// The string instructions, which access memory through RSI and/or RDI.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StringOp {
+    Movs,
+    Stos,
+    Lods,
+    Cmps,
+    Scas
+}
+
+/// A decoded string instruction: the operation, whether it has a REP prefix, the
+/// element size in bytes and any FS or GS override of its RSI operand.
+pub struct StringInstruction {
+    pub op: StringOp,
+    pub rep: bool,
+    pub size: u16,
+    pub segment: Option<Segment>
+}
+
+impl StringInstruction {
+    /// The address of the RSI operand, with the base of the segment it's in.
+    pub fn source(&self, regs: &libc::user_regs_struct) -> u64 {
+        let base = match self.segment {
+            Some(Segment::Fs) => regs.fs_base,
+            Some(Segment::Gs) => regs.gs_base,
+            None => 0
+        };
+        regs.rsi.wrapping_add(base)
+    }
+}
+
+/// Recognize a string instruction. Decoders give these their short form (STOSB,
+/// MOVSQ) without operands, which is also how the string MOVSD and CMPSD are told
+/// apart from the SSE ones.
+pub fn string_instruction(instruction: &Instruction) -> Option<StringInstruction> {
+    let mnemonic = instruction.mnemonic.as_str();
+    if mnemonic.len() < 4 || !instruction.operands.is_empty() {
+        return None;
+    }
+
+    let op = match &mnemonic[..4] {
+        "MOVS" => StringOp::Movs,
+        "STOS" => StringOp::Stos,
+        "LODS" => StringOp::Lods,
+        "CMPS" => StringOp::Cmps,
+        "SCAS" => StringOp::Scas,
+        _ => return None
+    };
+    let size = match &mnemonic[4..] {
+        "B" => 1,
+        "W" => 2,
+        "D" => 4,
+        "Q" => 8,
+        _ => return None
+    };
+
+    Some(StringInstruction { op, rep: instruction.rep, size, segment: instruction.segment })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{Operand, OperandKind, Register};
+
+    #[test]
+    fn classify_operands() {
+        use AccessKind::*;
+        let cases: &[(&str, usize, Option<AccessKind>)] = &[
+            ("MOV", 0, Some(Store)),
+            ("MOV", 1, Some(Load)),
+            ("CMP", 0, Some(Load)),
+            ("CMP", 1, Some(Load)),
+            ("ADD", 0, Some(LoadStore)),
+            ("ADD", 1, Some(Load)),
+            ("XCHG", 0, Some(LoadStore)),
+            ("XCHG", 1, Some(LoadStore)),
+            ("LEA", 1, None),
+            ("NOP", 0, None),
+            ("SETE", 0, Some(Store)),
+            ("SETNB", 0, Some(Store)),
+            ("VMOVDQU", 0, Some(Store)),
+            ("VMOVAPS", 1, Some(Load)),
+            ("MASKMOVDQU", 0, Some(Store)),
+            ("POP", 0, Some(Store)),
+            ("PUSH", 0, Some(Load)),
+            ("PXOR", 1, Some(Load))
+        ];
+        for &(mnemonic, operand, expected) in cases {
+            assert_eq!(classify(mnemonic, operand), expected, "{} operand {}", mnemonic, operand);
+        }
+    }
+
+    fn instruction(mnemonic: &str, rep: bool, operands: Vec<Operand>) -> Instruction {
+        Instruction { len: 1, mnemonic: mnemonic.to_string(), rep, segment: None, operands, writes_flags: false }
+    }
+
+    #[test]
+    fn string_mnemonics() {
+        let cases: &[(&str, StringOp, u16)] = &[
+            ("MOVSB", StringOp::Movs, 1),
+            ("STOSW", StringOp::Stos, 2),
+            ("LODSD", StringOp::Lods, 4),
+            ("CMPSQ", StringOp::Cmps, 8),
+            ("SCASB", StringOp::Scas, 1),
+            ("MOVSD", StringOp::Movs, 4),
+            ("CMPSD", StringOp::Cmps, 4)
+        ];
+        for &(mnemonic, op, size) in cases {
+            let string = string_instruction(&instruction(mnemonic, true, vec![])).unwrap();
+            assert_eq!((string.op, string.rep, string.size), (op, true, size), "{}", mnemonic);
+        }
+
+        // The SSE MOVSD and CMPSD have operands.
+        let xmm = |n| Operand { kind: OperandKind::Register(Register::Vector(n)), size: 16 };
+        assert!(string_instruction(&instruction("MOVSD", false, vec![xmm(0), xmm(1)])).is_none());
+        assert!(string_instruction(&instruction("CMPSD", false, vec![xmm(0), xmm(1)])).is_none());
+        for mnemonic in ["MOVSX", "MOVSXD", "MOVS", "STO", "SCASX", "MOV"] {
+            assert!(string_instruction(&instruction(mnemonic, false, vec![])).is_none(), "{}", mnemonic);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::ptrace;
+use crate::ptrace::Tracee;
+
+const INT3: u64 = 0xcc;
+
+/// An `int3` written over the first byte of an instruction in the tracee.
+pub struct Breakpoint {
+    pub addr: u64,
+    // The word at `addr` before the breakpoint was written.
+    original: u64
+}
+
+impl Breakpoint {
+    pub fn insert(tracee: Tracee, addr: u64) -> ptrace::Result<Breakpoint> {
+        let original = tracee.peek(addr)?;
+        tracee.poke(addr, (original & !0xff) | INT3)?;
+        Ok(Breakpoint { addr, original })
+    }
+
+    /// Put back the original instruction byte.
+    pub fn remove(self, tracee: Tracee) -> ptrace::Result<()> {
+        self.disarm(tracee)
+    }
+
+    /// Put back the original instruction byte, keeping the breakpoint around so
+    /// that it can be `arm`ed again.
+    pub fn disarm(&self, tracee: Tracee) -> ptrace::Result<()> {
+        let word = tracee.peek(self.addr)?;
+        tracee.poke(self.addr, (word & !0xff) | (self.original & 0xff))
+    }
+
+    /// Write the `int3` back after `disarm`.
+    pub fn arm(&self, tracee: Tracee) -> ptrace::Result<()> {
+        let word = tracee.peek(self.addr)?;
+        tracee.poke(self.addr, (word & !0xff) | INT3)
+    }
+}
+
+/// Marks the start and end of each call to a function with breakpoints on its
+/// entry and on the return address of the current call, so the function can be
+/// traced without the code around it raising signals.
+pub struct FunctionMarkers {
+    entry: u64,
+    entry_bp: Option<Breakpoint>,
+    return_bp: Option<Breakpoint>,
+    // The stack pointer once the current call has returned, to tell the return
+    // from that of a recursive call that happens to return to the same address.
+    return_rsp: u64,
+    // Where the return breakpoint of the last call was.
+    last_return: Option<u64>,
+    // Whether the return breakpoint is taken out for the thread making the call
+    // to step over the instruction under it.
+    return_lifted: bool
+}
+
+impl FunctionMarkers {
+    pub fn new(tracee: Tracee, entry: u64) -> ptrace::Result<FunctionMarkers> {
+        Ok(FunctionMarkers {
+            entry,
+            entry_bp: Some(Breakpoint::insert(tracee, entry)?),
+            return_bp: None,
+            return_rsp: 0,
+            last_return: None,
+            return_lifted: false
+        })
+    }
+
+    /// Remove whichever breakpoint is in place, before detaching.
+    pub fn remove(self, tracee: Tracee) -> ptrace::Result<()> {
+        if let Some(bp) = self.entry_bp {
+            bp.remove(tracee)?;
+        }
+        if let Some(bp) = self.return_bp {
+            bp.remove(tracee)?;
+        }
+        Ok(())
+    }
+
+    /// True between `enter` and `returned`.
+    pub fn in_call(&self) -> bool {
+        self.return_bp.is_some()
+    }
+
+    /// Check whether a SIGTRAP was the entry breakpoint. If it was, the breakpoint
+    /// is removed, `regs.rip` (which is past the `int3`) is rewound in the tracee
+    /// and the caller's return address is breakpointed instead.
+    pub fn enter(&mut self, tracee: Tracee, regs: &mut libc::user_regs_struct) -> ptrace::Result<bool> {
+        if self.in_call() || regs.rip != self.entry + 1 {
+            return Ok(false);
+        }
+        self.entry_bp.take().unwrap().remove(tracee)?;
+        regs.rip = self.entry;
+        tracee.set_regs(regs)?;
+
+        let return_addr = tracee.peek(regs.rsp)?;
+        self.return_rsp = regs.rsp + 8;
+        self.return_bp = Some(Breakpoint::insert(tracee, return_addr)?);
+        Ok(true)
+    }
+
+    /// Let a thread other than the one making the call past the return breakpoint,
+    /// if that's what it stopped on. A thread that hit the entry breakpoint or an
+    /// earlier return breakpoint just as another thread took it out is backed up
+    /// to run the original instruction.
+    pub fn step_over(&mut self, tracee: Tracee, regs: &mut libc::user_regs_struct) -> ptrace::Result<()> {
+        let addr = match self.return_bp {
+            Some(ref bp) if regs.rip == bp.addr + 1 => bp.addr,
+            _ => {
+                let entry = if self.entry_bp.is_none() { Some(self.entry) } else { None };
+                if entry.iter().chain(self.last_return.iter()).any(|&addr| regs.rip == addr + 1) {
+                    regs.rip -= 1;
+                    tracee.set_regs(regs)?;
+                }
+                return Ok(());
+            }
+        };
+        self.return_bp.take().unwrap().remove(tracee)?;
+        regs.rip = addr;
+        tracee.set_regs(regs)?;
+        tracee.step(0)?;
+        tracee.wait_event()?;
+        self.return_bp = Some(Breakpoint::insert(tracee, addr)?);
+        Ok(())
+    }
+
+    /// Check, before single-stepping during a call, whether the call has returned.
+    /// If it has, the return breakpoint is swapped back for the entry one. The
+    /// tracee is stopped on the return breakpoint but hasn't executed it yet.
+    pub fn returned(&mut self, tracee: Tracee, regs: &libc::user_regs_struct) -> ptrace::Result<bool> {
+        if self.return_lifted {
+            self.return_lifted = false;
+            if let Some(ref bp) = self.return_bp {
+                bp.arm(tracee)?;
+            }
+        }
+        let at_return = match self.return_bp {
+            Some(ref bp) => regs.rip == bp.addr && regs.rsp == self.return_rsp,
+            None => false
+        };
+        if at_return {
+            let bp = self.return_bp.take().unwrap();
+            self.last_return = Some(bp.addr);
+            bp.remove(tracee)?;
+            self.entry_bp = Some(Breakpoint::insert(tracee, self.entry)?);
+        }
+        Ok(at_return)
+    }
+    /// Take the return breakpoint out if the thread making the call is stopped on
+    /// it without having returned, as a recursive call through the same call site
+    /// does, so that the next single step runs the original instruction. The
+    /// following call to `returned` puts it back.
+    pub fn lift_return(&mut self, tracee: Tracee, regs: &libc::user_regs_struct) -> ptrace::Result<()> {
+        if let Some(ref bp) = self.return_bp {
+            if regs.rip == bp.addr && !self.return_lifted {
+                bp.disarm(tracee)?;
+                self.return_lifted = true;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::ConstantTimeTarget;
+use crate::Checks;
+use crate::Config;
+use crate::leakage::LeakageModel;
+use crate::normalize::Normalization;
+use crate::latency::LatencyPolicy;
+use crate::inputs::InputClass;
+use crate::symbols::Symbolizer;
+use crate::report;
+use crate::tracefile;
+use crate::timing::MIN_MEASUREMENTS;
+use crate::{timing_test, trace_target, trace_process, trace_program};
+
+const USAGE: &str = "\
+Usage: rust-fixed-time-tester [options] [target...]
+       rust-fixed-time-tester [options] diff TRACE TRACE
+       rust-fixed-time-tester [options] -f FUNCTION attach PID
+       rust-fixed-time-tester [options] -f FUNCTION exec PROGRAM [ARGS...]
+
+Traces each named target (or every registered target if none are named) and
+exits with a non-zero status if any of them behaved differently between runs.
+`diff` compares two traces saved with --save, using the --check, --model and
+--normalize options. `attach` traces --iterations calls to FUNCTION in a running
+process and then detaches from it. `exec` does the same for
+a program it starts, which is then left to run to completion.
+
+Options:
+    -l, --list              List the registered targets and exit
+    -n, --iterations N      Number of traced runs per target (default: 64)
+    -s, --seed N            Seed for input generation (default: random)
+    -i, --inputs CLASSES    Only use these (comma separated) input classes:
+                            random, fixed-vs-random, fixed-vs-fixed, zero,
+                            ones, low-weight, bit-flip or edge (default: every
+                            class the target supports)
+    -f, --function NAME     Trace calls to this function (mangled, or demangled
+                            without the hash) made by each target rather than
+                            its whole run
+    -c, --check CHECK       What to compare: instructions, memory or both
+                            (default: both)
+    -m, --model MODEL       How much of a memory access is observable: exact,
+                            line (64 byte cache lines), page (4 KiB pages) or
+                            set[:LINE_SIZE:SETS] (cache set index, default
+                            64:64) (default: exact)
+    -a, --normalize HOW     Compare addresses as absolute (none), as offsets
+                            into their mapping (mapping), or also as offsets
+                            into the input buffers (allocation)
+                            (default: none)
+    -v, --variable-latency LIST
+                            Fail if any of a comma separated list of
+                            instructions is executed. `default` (division and
+                            square root) and `strict` (also multiplies and
+                            denormal sensitive floating point) name the builtin
+                            lists, and can be combined with extra mnemonics
+    -t, --taint             Track each target's secret inputs and fail on any
+                            branch, memory address or repeat count that
+                            depends on them
+    -T, --timing            Instead of tracing, time each target natively on a
+                            fixed input versus random inputs and apply Welch's
+                            t-test to the cycle counts
+    -M, --measurements N    Number of measurements in timing mode
+                            (at least 100, default: 1000000)
+    --t-threshold T         |t| above which timing mode reports a leak
+                            (default: 4.5)
+    --save DIR              Save every traced run to DIR/<target>-<run>.trace
+    --reference FILE        Compare runs against a saved trace instead of the
+                            first run
+    --golden DIR            Fail if any run's trace shape (instruction count
+                            and sequence, memory access count and kinds)
+                            differs from the one recorded in DIR/<target>.golden.
+                            Targets without one have it recorded
+    --update-golden         Re-record the shapes in the --golden directory
+    -e, --env NAME=VALUE    Set an environment variable for `exec`
+    -h, --help              Show this message";
+
+enum Command {
+    Help,
+    List,
+    Run(Config, Vec<String>),
+    Diff(Config, PathBuf, PathBuf),
+    Attach(Config, libc::pid_t),
+    Exec(Config, PathBuf, Vec<String>, Vec<(String, String)>)
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let mut config = Config::default();
+    let mut names = Vec::new();
+    let mut env = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg = arg.as_str();
+        match arg {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-l" | "--list" => return Ok(Command::List),
+            "-t" | "--taint" => config.taint = true,
+            "-T" | "--timing" => config.timing = true,
+            "--update-golden" => config.update_golden = true,
+            "-n" | "--iterations" | "-s" | "--seed" | "-i" | "--inputs" |
+            "-c" | "--check" | "-m" | "--model" | "-a" | "--normalize" |
+            "-v" | "--variable-latency" | "-M" | "--measurements" | "--t-threshold" |
+            "--save" | "--reference" | "--golden" | "-f" | "--function" | "-e" | "--env" => {
+                let value = match iter.next() {
+                    Some(value) => value.as_str(),
+                    None => return Err(format!("{} requires a value", arg))
+                };
+                match arg {
+                    "-n" | "--iterations" => match value.parse() {
+                        Ok(n) => config.iterations = n,
+                        Err(_) => return Err(format!("Invalid iteration count: {}", value))
+                    },
+                    "-s" | "--seed" => match value.parse() {
+                        Ok(n) => config.seed = n,
+                        Err(_) => return Err(format!("Invalid seed: {}", value))
+                    },
+                    "-m" | "--model" => match LeakageModel::parse(value) {
+                        Some(model) => config.model = model,
+                        None => return Err(format!("Unknown leakage model: {}", value))
+                    },
+                    "-a" | "--normalize" => match Normalization::parse(value) {
+                        Some(normalization) => config.normalization = normalization,
+                        None => return Err(format!("Unknown normalization: {}", value))
+                    },
+                    "-v" | "--variable-latency" => match LatencyPolicy::parse(value) {
+                        Some(policy) => config.latency = Some(policy),
+                        None => return Err(format!("Invalid instruction list: {}", value))
+                    },
+                    "-i" | "--inputs" => {
+                        let mut classes = Vec::new();
+                        for name in value.split(',') {
+                            match InputClass::parse(name) {
+                                Some(class) => classes.push(class),
+                                None => return Err(format!("Unknown input class: {}", name))
+                            }
+                        }
+                        config.input_classes = Some(classes);
+                    }
+                    "-M" | "--measurements" => match value.parse() {
+                        Ok(n) if n >= MIN_MEASUREMENTS => config.measurements = n,
+                        _ => return Err(format!("Invalid measurement count: {} (at least {} are needed)",
+                                                value, MIN_MEASUREMENTS))
+                    },
+                    "--t-threshold" => match value.parse() {
+                        Ok(t) => config.t_threshold = t,
+                        Err(_) => return Err(format!("Invalid threshold: {}", value))
+                    },
+                    "--save" => config.save_dir = Some(PathBuf::from(value)),
+                    "--reference" => config.reference = Some(PathBuf::from(value)),
+                    "--golden" => config.golden_dir = Some(PathBuf::from(value)),
+                    "-f" | "--function" => config.function = Some(value.to_string()),
+                    "-e" | "--env" => {
+                        match value.split_once('=') {
+                            Some((name, value)) if !name.is_empty() => {
+                                env.push((name.to_string(), value.to_string()))
+                            }
+                            _ => return Err(format!("Invalid environment variable: {}", value))
+                        }
+                    }
+                    _ => config.checks = match value {
+                        "instructions" => Checks::Instructions,
+                        "memory" => Checks::Memory,
+                        "both" => Checks::Both,
+                        _ => return Err(format!("Unknown check: {}", value))
+                    }
+                }
+            }
+            // Everything after the program is passed to it as is.
+            "exec" if names.is_empty() => {
+                let program = match iter.next() {
+                    Some(program) => PathBuf::from(program),
+                    None => return Err("exec needs a program to run".to_string())
+                };
+                if config.function.is_none() {
+                    return Err("exec needs a --function to trace".to_string());
+                }
+                let args = iter.cloned().collect();
+                return Ok(Command::Exec(config, program, args, env));
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ => names.push(arg.to_string())
+        }
+    }
+
+    if names.first().map(|n| n.as_str()) == Some("diff") {
+        if names.len() != 3 {
+            return Err("diff takes two trace files".to_string());
+        }
+        return Ok(Command::Diff(config, PathBuf::from(&names[1]), PathBuf::from(&names[2])));
+    }
+    if names.first().map(|n| n.as_str()) == Some("attach") {
+        if names.len() != 2 {
+            return Err("attach takes a process id".to_string());
+        }
+        if config.function.is_none() {
+            return Err("attach needs a --function to trace".to_string());
+        }
+        return match names[1].parse() {
+            Ok(pid) => Ok(Command::Attach(config, pid)),
+            Err(_) => Err(format!("Invalid process id: {}", names[1]))
+        };
+    }
+
+    Ok(Command::Run(config, names))
+}
+
+// Compare two saved traces, symbolizing with the files that are on disk now.
+fn diff(config: &Config, path_a: &Path, path_b: &Path) -> i32 {
+    let (header_a, a) = match tracefile::read(path_a) {
+        Ok(trace) => trace,
+        Err(e) => {
+            println!("Couldn't read {}: {}", path_a.display(), e);
+            return 2;
+        }
+    };
+    let (header_b, b) = match tracefile::read(path_b) {
+        Ok(trace) => trace,
+        Err(e) => {
+            println!("Couldn't read {}: {}", path_b.display(), e);
+            return 2;
+        }
+    };
+    if header_a.target != header_b.target {
+        println!("Comparing traces of different targets: {} and {}", header_a.target, header_b.target);
+    }
+    if header_a.build_id != header_b.build_id {
+        println!("The traces were recorded from different builds");
+    }
+
+    match report::compare(&a, &b, config) {
+        None => {
+            println!("The traces are the same");
+            0
+        }
+        Some(divergence) => {
+            let symbols = Symbolizer::from_maps(&a.maps, header_a.build_id.as_deref());
+            report::print_divergence(&a, &path_a.display().to_string(), &b, &path_b.display().to_string(),
+                                     &divergence, &symbols);
+            1
+        }
+    }
+}
+
+/// Parse the command line and trace the selected targets, or diff two saved traces.
+/// Returns the process exit status: 0 if every target was constant time (or the
+/// traces matched), 1 if any diverged and 2 for usage errors.
+///
+/// Crates with their own targets can call this from their own `main` with the
+/// builtin targets plus their own.
+pub fn run(mut targets: Vec<Box<dyn ConstantTimeTarget>>) -> i32 {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (config, names) = match parse_args(&args) {
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Ok(Command::List) => {
+            for target in &targets {
+                println!("{}", target.name());
+            }
+            return 0;
+        }
+        Ok(Command::Run(config, names)) => (config, names),
+        Ok(Command::Diff(config, a, b)) => return diff(&config, &a, &b),
+        Ok(Command::Attach(config, pid)) => {
+            let function = config.function.clone().unwrap();
+            return if trace_process(pid, &function, &config) { 0 } else { 1 };
+        }
+        Ok(Command::Exec(config, program, args, env)) => {
+            let function = config.function.clone().unwrap();
+            let passed = trace_program(&program, &args, &env, &function, &config);
+            return if passed { 0 } else { 1 };
+        }
+        Err(msg) => {
+            println!("{}\n\n{}", msg, USAGE);
+            return 2;
+        }
+    };
+
+    for name in &names {
+        if !targets.iter().any(|t| t.name() == name) {
+            println!("Unknown target: {} (use --list to see the registered targets)", name);
+            return 2;
+        }
+    }
+
+    println!("Using seed {}", config.seed);
+
+    let mut failed = Vec::new();
+    for target in targets.iter_mut() {
+        if !names.is_empty() && !names.iter().any(|n| n == target.name()) {
+            continue;
+        }
+        let passed = if config.timing {
+            println!("Timing {}", target.name());
+            timing_test(&mut **target, &config)
+        } else {
+            println!("Tracing {}", target.name());
+            trace_target(&mut **target, &config)
+        };
+        if !passed {
+            failed.push(target.name().to_string());
+        }
+    }
+
+    if failed.is_empty() {
+        0
+    } else {
+        println!("Targets that were not constant time: {}", failed.join(", "));
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    fn run(args: &[&str]) -> (Config, Vec<String>) {
+        match parse(args) {
+            Ok(Command::Run(config, names)) => (config, names),
+            Ok(_) => panic!("{:?} isn't a run", args),
+            Err(e) => panic!("{:?}: {}", args, e)
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} parsed", args),
+            Err(e) => e
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let (config, names) = run(&[]);
+        assert_eq!(config.iterations, 64);
+        assert_eq!(config.checks, Checks::Both);
+        assert_eq!(config.model, LeakageModel::Exact);
+        assert!(!config.taint && !config.timing && !config.update_golden);
+        assert!(config.input_classes.is_none() && config.latency.is_none());
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn flags() {
+        let (config, names) = run(&["-n", "8", "aes", "-s", "1", "-c", "memory", "-m", "line",
+                                    "-a", "mapping", "-t", "-T", "-M", "1000", "--t-threshold",
+                                    "10.5", "-i", "zero,ones", "-v", "default,PMULUDQ",
+                                    "--save", "out", "--reference", "ref.trace", "--golden",
+                                    "golden", "--update-golden", "-f", "memcmp", "rc4"]);
+        assert_eq!(names, ["aes", "rc4"]);
+        assert_eq!(config.iterations, 8);
+        assert_eq!(config.seed, 1);
+        assert_eq!(config.checks, Checks::Memory);
+        assert_eq!(config.model, LeakageModel::CacheLine);
+        assert_eq!(config.normalization, Normalization::Mapping);
+        assert!(config.taint && config.timing && config.update_golden);
+        assert_eq!(config.measurements, 1000);
+        assert_eq!(config.t_threshold, 10.5);
+        assert_eq!(config.input_classes, Some(vec![InputClass::AllZero, InputClass::AllOnes]));
+        assert!(config.latency.unwrap().is_variable("PMULUDQ"));
+        assert_eq!(config.save_dir, Some(PathBuf::from("out")));
+        assert_eq!(config.reference, Some(PathBuf::from("ref.trace")));
+        assert_eq!(config.golden_dir, Some(PathBuf::from("golden")));
+        assert_eq!(config.function.as_deref(), Some("memcmp"));
+    }
+
+    #[test]
+    fn long_flags() {
+        let (config, _) = run(&["--iterations", "3", "--check", "instructions", "--model", "page",
+                                "--normalize", "none", "--taint", "--function", "f"]);
+        assert_eq!(config.iterations, 3);
+        assert_eq!(config.checks, Checks::Instructions);
+        assert_eq!(config.model, LeakageModel::Page);
+        assert_eq!(config.normalization, Normalization::Absolute);
+        assert!(config.taint);
+        assert_eq!(config.function.as_deref(), Some("f"));
+    }
+
+    #[test]
+    fn help_and_list() {
+        assert!(matches!(parse(&["aes", "-h"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["--help", "--bogus"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["-l"]), Ok(Command::List)));
+        assert!(matches!(parse(&["--list"]), Ok(Command::List)));
+    }
+
+    #[test]
+    fn missing_value() {
+        assert_eq!(error(&["-n"]), "-n requires a value");
+        assert_eq!(error(&["aes", "--function"]), "--function requires a value");
+        assert_eq!(error(&["-e"]), "-e requires a value");
+    }
+
+    #[test]
+    fn unknown_option() {
+        assert_eq!(error(&["--bogus"]), "Unknown option: --bogus");
+        assert_eq!(error(&["aes", "-x", "rc4"]), "Unknown option: -x");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(error(&["-n", "x"]), "Invalid iteration count: x");
+        assert_eq!(error(&["-s", "-1"]), "Invalid seed: -1");
+        assert_eq!(error(&["-c", "cache"]), "Unknown check: cache");
+        assert_eq!(error(&["-m", "lines"]), "Unknown leakage model: lines");
+        assert_eq!(error(&["-a", "heap"]), "Unknown normalization: heap");
+        assert_eq!(error(&["-v", "DIV,,MUL"]), "Invalid instruction list: DIV,,MUL");
+        assert_eq!(error(&["-i", "zero,none"]), "Unknown input class: none");
+        assert_eq!(error(&["-M", "10"]), "Invalid measurement count: 10 (at least 100 are needed)");
+        assert_eq!(error(&["--t-threshold", "high"]), "Invalid threshold: high");
+        assert_eq!(error(&["-e", "=1"]), "Invalid environment variable: =1");
+    }
+
+    #[test]
+    fn diff() {
+        match parse(&["-m", "page", "diff", "a.trace", "b.trace"]) {
+            Ok(Command::Diff(config, a, b)) => {
+                assert_eq!(config.model, LeakageModel::Page);
+                assert_eq!((a, b), (PathBuf::from("a.trace"), PathBuf::from("b.trace")));
+            }
+            _ => panic!("not a diff")
+        }
+        assert_eq!(error(&["diff", "a.trace"]), "diff takes two trace files");
+    }
+
+    #[test]
+    fn attach() {
+        match parse(&["attach", "1234", "-f", "f"]) {
+            Ok(Command::Attach(_, pid)) => assert_eq!(pid, 1234),
+            _ => panic!("not an attach")
+        }
+        assert_eq!(error(&["attach", "1234"]), "attach needs a --function to trace");
+        assert_eq!(error(&["attach", "-f", "f"]), "attach takes a process id");
+        assert_eq!(error(&["attach", "pid", "-f", "f"]), "Invalid process id: pid");
+    }
+
+    #[test]
+    fn exec() {
+        // Options after the program belong to it.
+        match parse(&["-e", "A=b=c", "-f", "f", "exec", "./prog", "-n", "x", "--bogus"]) {
+            Ok(Command::Exec(config, program, args, env)) => {
+                assert_eq!(config.function.as_deref(), Some("f"));
+                assert_eq!(program, PathBuf::from("./prog"));
+                assert_eq!(args, ["-n", "x", "--bogus"]);
+                assert_eq!(env, [("A".to_string(), "b=c".to_string())]);
+            }
+            _ => panic!("not an exec")
+        }
+        assert_eq!(error(&["-f", "f", "exec"]), "exec needs a program to run");
+        assert_eq!(error(&["exec", "./prog"]), "exec needs a --function to trace");
+    }
+}
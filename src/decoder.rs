@@ -0,0 +1,215 @@
+// The tracer only needs a few facts about each instruction it steps over: its
+// mnemonic, its operands and how to find the memory they refer to. This module
+// describes those independently of the disassembler that produced them, so the
+// backend can be chosen when the crate is built: iced-x86 by default, or the
+// distorm3 C library with the `distorm` feature.
+
+#[cfg(feature = "distorm")]
+pub use crate::distorm::Distorm as Backend;
+#[cfg(not(feature = "distorm"))]
+pub use crate::iced::Iced as Backend;
+
+/// A register named by an operand.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Register {
+    /// One of the 16 general purpose registers, numbered in encoding order (RAX,
+    /// RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8 to R15), of which `size` bytes are
+    /// used. `high` is set for AH, CH, DH and BH.
+    Gpr { num: u8, size: u8, high: bool },
+    /// XMM, YMM or ZMM register `n`.
+    Vector(u8),
+    /// Anything else (segment, x87, MMX, control and mask registers), none of
+    /// which are tracked.
+    Other
+}
+
+/// The segments that have a base address in 64-bit mode.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Segment {
+    Fs,
+    Gs
+}
+
+/// The parts of a memory operand's address. RIP-relative operands are resolved
+/// by the decoder, so they only have a displacement.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Memory {
+    pub base: Option<Register>,
+    pub index: Option<Register>,
+    /// 1, 2, 4 or 8.
+    pub scale: u8,
+    pub disp: u64,
+    /// Set for FS and GS overrides; the other segments are flat.
+    pub segment: Option<Segment>,
+    /// Whether the address is computed with 32-bit registers (a 0x67 prefix),
+    /// which truncates it before the segment base is added.
+    pub addr32: bool
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OperandKind {
+    Register(Register),
+    Memory(Memory),
+    Immediate(u64),
+    /// Branch targets, far pointers and the vector-indexed memory of gathers,
+    /// which has no single address.
+    Other
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Operand {
+    pub kind: OperandKind,
+    /// Width of the operand in bytes, for registers, memory and immediates (once
+    /// sign extended).
+    pub size: u16
+}
+
+/// A decoded instruction.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    /// Length of the instruction in bytes.
+    pub len: usize,
+    /// The upper case Intel mnemonic, without prefixes. String instructions have
+    /// their short form (STOSB, MOVSQ) and no operands, which is also how the
+    /// string MOVSD and CMPSD are told apart from the SSE ones.
+    pub mnemonic: String,
+    /// Whether the instruction has a REP, REPE or REPNE prefix.
+    pub rep: bool,
+    /// The FS or GS override of a string instruction, which applies to its RSI
+    /// operand. Other instructions have it in their memory operands.
+    pub segment: Option<Segment>,
+    /// The explicit operands, in Intel order.
+    pub operands: Vec<Operand>,
+    /// Whether the instruction changes any of the status flags.
+    pub writes_flags: bool
+}
+
+/// A disassembler that can take apart a single x86-64 instruction.
+pub trait Decoder {
+    /// Decode the instruction at the start of `code`, which is located at `rip`.
+    /// Returns None if the bytes aren't a valid instruction.
+    fn decode(&self, rip: u64, code: &[u8]) -> Option<Instruction>;
+
+    /// Intel syntax text for the instruction at the start of `code`.
+    fn format(&self, rip: u64, code: &[u8]) -> Option<String>;
+}
+
+/// Decode the instruction at `rip` with the backend the crate was built with.
+pub fn decode(rip: u64, code: &[u8]) -> Option<Instruction> {
+    Backend.decode(rip, code)
+}
+
+/// Disassemble the single instruction in `code`, which was located at `rip`.
+pub fn disassemble(rip: u64, code: &[u8]) -> String {
+    Backend.format(rip, code).unwrap_or_else(|| "(bad)".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iced::Iced;
+
+    const RIP: u64 = 0x1000;
+
+    fn gpr(num: u8, size: u8) -> Register {
+        Register::Gpr { num, size, high: false }
+    }
+
+    fn reg(reg: Register, size: u16) -> Operand {
+        Operand { kind: OperandKind::Register(reg), size }
+    }
+
+    fn mem(base: Option<Register>, index: Option<Register>, scale: u8, disp: u64, size: u16) -> Operand {
+        let addr32 = matches!(base, Some(Register::Gpr { size: 4, .. }));
+        Operand { kind: OperandKind::Memory(Memory { base, index, scale, disp, segment: None, addr32 }), size }
+    }
+
+    fn instruction(len: usize, mnemonic: &str, operands: Vec<Operand>, writes_flags: bool) -> Instruction {
+        Instruction { len, mnemonic: mnemonic.to_string(), rep: false, segment: None, operands, writes_flags }
+    }
+
+    // Instructions both backends describe the same way.
+    fn common() -> Vec<(&'static [u8], Instruction)> {
+        let mut fs_load = mem(None, None, 1, 0x28, 8);
+        if let OperandKind::Memory(ref mut m) = fs_load.kind {
+            m.segment = Some(Segment::Fs);
+        }
+        vec![
+            // mov rax, [rsp+8]
+            (&[0x48, 0x8b, 0x44, 0x24, 0x08],
+             instruction(5, "MOV", vec![reg(gpr(0, 8), 8), mem(Some(gpr(4, 8)), None, 1, 8, 8)], false)),
+            // mov eax, [rip+0x10]
+            (&[0x8b, 0x05, 0x10, 0x00, 0x00, 0x00],
+             instruction(6, "MOV", vec![reg(gpr(0, 4), 4), mem(None, None, 1, RIP + 6 + 0x10, 4)], false)),
+            // mov rax, fs:[0x28]
+            (&[0x64, 0x48, 0x8b, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00],
+             instruction(9, "MOV", vec![reg(gpr(0, 8), 8), fs_load], false)),
+            // movzx eax, byte [rbx+r9*4]
+            (&[0x42, 0x0f, 0xb6, 0x04, 0x8b],
+             instruction(5, "MOVZX", vec![reg(gpr(0, 4), 4), mem(Some(gpr(3, 8)), Some(gpr(9, 8)), 4, 0, 1)], false)),
+            // mov eax, [ebx]
+            (&[0x67, 0x8b, 0x03],
+             instruction(3, "MOV", vec![reg(gpr(0, 4), 4), mem(Some(gpr(3, 4)), None, 1, 0, 4)], false)),
+            // add eax, ebx
+            (&[0x01, 0xd8], instruction(2, "ADD", vec![reg(gpr(0, 4), 4), reg(gpr(3, 4), 4)], true)),
+            // mov al, ah
+            (&[0x88, 0xe0],
+             instruction(2, "MOV", vec![reg(gpr(0, 1), 1), reg(Register::Gpr { num: 0, size: 1, high: true }, 1)],
+                         false)),
+            // imul rax, rcx, 7
+            (&[0x48, 0x6b, 0xc1, 0x07],
+             instruction(4, "IMUL", vec![reg(gpr(0, 8), 8), reg(gpr(1, 8), 8),
+                                         Operand { kind: OperandKind::Immediate(7), size: 8 }], true)),
+            // rep stosq
+            (&[0xf3, 0x48, 0xab], Instruction { rep: true, ..instruction(3, "STOSQ", vec![], false) }),
+            // movsb, and with an FS override
+            (&[0xa4], instruction(1, "MOVSB", vec![], false)),
+            (&[0x64, 0xa4], Instruction { segment: Some(Segment::Fs), ..instruction(2, "MOVSB", vec![], false) }),
+            // ret
+            (&[0xc3], instruction(1, "RET", vec![], false))
+        ]
+    }
+
+    #[test]
+    fn iced() {
+        for (code, expected) in common() {
+            assert_eq!(Iced.decode(RIP, code), Some(expected), "{:02x?}", code);
+        }
+    }
+
+    #[test]
+    fn iced_vector() {
+        let cases: Vec<(&[u8], Instruction)> = vec![
+            // movsd xmm0, xmm1, which isn't the string MOVSD
+            (&[0xf2, 0x0f, 0x10, 0xc1],
+             instruction(4, "MOVSD", vec![reg(Register::Vector(0), 16), reg(Register::Vector(1), 16)], false)),
+            // maskmovdqu xmm0, xmm1, which stores to [rdi]
+            (&[0x66, 0x0f, 0xf7, 0xc1],
+             instruction(4, "MASKMOVDQU", vec![mem(Some(gpr(7, 8)), None, 1, 0, 16),
+                                              reg(Register::Vector(0), 16), reg(Register::Vector(1), 16)],
+                         false)),
+            // vgatherdps ymm0, [rax+ymm1*4], ymm2, which has no single address
+            (&[0xc4, 0xe2, 0x6d, 0x92, 0x04, 0x88],
+             instruction(6, "VGATHERDPS", vec![reg(Register::Vector(0), 32), Operand { kind: OperandKind::Other, size: 0 },
+                                              reg(Register::Vector(2), 32)], false))
+        ];
+        for (code, expected) in cases {
+            assert_eq!(Iced.decode(RIP, code), Some(expected), "{:02x?}", code);
+        }
+    }
+
+    #[test]
+    fn iced_invalid() {
+        assert_eq!(Iced.decode(RIP, &[0x00]), None);
+        assert_eq!(Iced.decode(RIP, &[0x48]), None);
+    }
+
+    #[cfg(feature = "distorm")]
+    #[test]
+    fn distorm_matches_iced() {
+        use crate::distorm::Distorm;
+        for (code, expected) in common() {
+            assert_eq!(Distorm.decode(RIP, code), Some(expected), "{:02x?}", code);
+        }
+    }
+}
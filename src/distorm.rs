@@ -1,13 +1,20 @@
-use libc;
+use crate::decoder;
+use crate::decoder::{Decoder, Instruction, Memory, OperandKind, Register, Segment};
 
-use std::default::Default;
+// Bindings for distorm 3.3. The structs mirror the ones in its distorm.h, which
+// changed layout in later versions.
 
-pub const FLAG_NOT_DECODABLE: u16 = -1 as u16;
+pub const FLAG_NOT_DECODABLE: u16 = u16::MAX;
+
+// Set in DInst.segment when the segment is the instruction's default rather than
+// an explicit override.
+pub const SEGMENT_DEFAULT: u8 = 0x80;
 
 pub type Value = u64;
 pub type Offset = u64;
 
 #[repr(C)]
+#[derive(Copy, Clone)]
 pub enum DecodeType {
     Decode16Bits,
     Decode32Bits,
@@ -15,6 +22,7 @@ pub enum DecodeType {
 }
 
 #[repr(C)]
+#[allow(non_camel_case_types)]
 pub enum DecodeResult {
     DECRES_NONE,
     DECRES_SUCCESS,
@@ -23,8 +31,12 @@ pub enum DecodeResult {
     DECRES_FILTERED
 }
 
+// distorm fills in the operand and register types as plain bytes, which are only
+// turned into the enums below once they are known to be in range.
+
 #[repr(u8)]
-#[deriving(Copy)]
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OperandType {
     O_NONE,
     O_REG,
@@ -38,8 +50,27 @@ pub enum OperandType {
     O_PTR
 }
 
+impl OperandType {
+    pub fn from_u8(typ: u8) -> Option<OperandType> {
+        match typ {
+            0 => Some(OperandType::O_NONE),
+            1 => Some(OperandType::O_REG),
+            2 => Some(OperandType::O_IMM),
+            3 => Some(OperandType::O_IMM1),
+            4 => Some(OperandType::O_IMM2),
+            5 => Some(OperandType::O_DISP),
+            6 => Some(OperandType::O_SMEM),
+            7 => Some(OperandType::O_MEM),
+            8 => Some(OperandType::O_PC),
+            9 => Some(OperandType::O_PTR),
+            _ => None
+        }
+    }
+}
+
 #[repr(u8)]
-#[deriving(Copy)]
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum RegisterType {
     R_RAX, R_RCX, R_RDX, R_RBX, R_RSP, R_RBP, R_RSI, R_RDI, R_R8, R_R9, R_R10, R_R11, R_R12, R_R13, R_R14, R_R15,
     R_EAX, R_ECX, R_EDX, R_EBX, R_ESP, R_EBP, R_ESI, R_EDI, R_R8D, R_R9D, R_R10D, R_R11D, R_R12D, R_R13D, R_R14D, R_R15D,
@@ -57,23 +88,27 @@ pub enum RegisterType {
     R_NONE = 255
 }
 
-#[deriving(Copy)]
+#[repr(C)]
+#[derive(Copy, Clone)]
 pub struct Operand {
-    pub typ: OperandType,
-    pub index: RegisterType,
+    /// An `OperandType`.
+    pub typ: u8,
+    /// A `RegisterType`.
+    pub index: u8,
     pub size: u16
 }
 
 impl Default for Operand {
     fn default() -> Operand {
         Operand {
-            typ: OperandType::O_NONE,
-            index: RegisterType::R_RAX,
+            typ: OperandType::O_NONE as u8,
+            index: RegisterType::R_RAX as u8,
             size: 0
         }
     }
 }
 
+#[repr(C)]
 pub struct CodeInfo {
     pub code_offset: Offset,
     pub next_offset: Offset,
@@ -88,7 +123,7 @@ impl Default for CodeInfo {
         CodeInfo {
             code_offset: 0,
             next_offset: 0,
-            code: 0 as *const u8,
+            code: std::ptr::null(),
             code_len: 0,
             decode_type: DecodeType::Decode16Bits,
             features: 0
@@ -96,6 +131,7 @@ impl Default for CodeInfo {
     }
 }
 
+#[repr(C)]
 pub struct DInst {
     pub imm: Value,
     pub disp: u64,
@@ -104,10 +140,11 @@ pub struct DInst {
     pub unused_prefixes_mask: u16,
     pub used_register_mask: u16,
     pub opcode: u16,
-    pub ops: [Operand, ..4],
+    pub ops: [Operand; 4],
     pub size: u8,
     pub segment: u8,
-    pub base: RegisterType,
+    /// A `RegisterType`.
+    pub base: u8,
     pub scale: u8,
     pub disp_size: u8,
     pub meta: u8,
@@ -126,10 +163,10 @@ impl Default for DInst {
             unused_prefixes_mask: 0,
             used_register_mask: 0,
             opcode: 0,
-            ops: [Default::default(), ..4],
+            ops: [Default::default(); 4],
             size: 0,
             segment: 0,
-            base: RegisterType::R_RAX,
+            base: RegisterType::R_RAX as u8,
             scale: 0,
             disp_size: 0,
             meta: 0,
@@ -140,23 +177,25 @@ impl Default for DInst {
     }
 }
 
-
+#[repr(C)]
 pub struct WString {
     pub length: libc::c_int,
 
     // This is a NULL terminated string
-    pub p: [libc::c_char, ..48]
+    pub p: [libc::c_char; 48]
 }
 
 impl Default for WString {
     fn default() -> WString {
         WString {
             length: 0,
-            p: [0, ..48]
+            p: [0; 48]
         }
     }
 }
 
+#[repr(C)]
+#[derive(Default)]
 pub struct DecodedInst {
     pub mnemonic: WString,
     pub operands: WString,
@@ -165,26 +204,214 @@ pub struct DecodedInst {
     pub offset: Offset
 }
 
-impl Default for DecodedInst {
-    fn default() -> DecodedInst {
-        DecodedInst {
-            mnemonic: Default::default(),
-            operands: Default::default(),
-            instruction_hex: Default::default(),
-            size: 0,
-            offset: 0
-        }
-    }
-}
-
 #[link(name = "distorm3")]
-extern {
+extern "C" {
     pub fn distorm_decompose64(
         code_info: *mut CodeInfo,
         result: *mut DInst,
         max_instructions: libc::c_int,
-        used_instruction_count: *mut libc::c_int) -> DecodeResult;
+        used_instruction_count: *mut libc::c_int) -> libc::c_int;
 
     pub fn distorm_format64(code_info: *mut CodeInfo, di: *mut DInst, result: *mut DecodedInst);
 }
- 
+
+/// Decodes with the distorm3 C library, which has to be installed.
+pub struct Distorm;
+
+fn code_info(rip: u64, code: &[u8]) -> CodeInfo {
+    CodeInfo {
+        code_offset: rip,
+        code: code.as_ptr(),
+        code_len: code.len() as libc::c_int,
+        decode_type: DecodeType::Decode64Bits,
+        ..Default::default()
+    }
+}
+
+fn decompose(rip: u64, code: &[u8]) -> Option<DInst> {
+    let mut code_info = code_info(rip, code);
+    let mut instruction: DInst = Default::default();
+    let mut used_instructions: libc::c_int = 0;
+
+    let result = unsafe {
+        distorm_decompose64(
+                &mut code_info as *mut CodeInfo,
+                &mut instruction as *mut DInst,
+                1,
+                &mut used_instructions as *mut libc::c_int)
+    };
+    if result != DecodeResult::DECRES_SUCCESS as libc::c_int && result != DecodeResult::DECRES_MEMORYERR as libc::c_int {
+        return None;
+    }
+    if used_instructions != 1 || instruction.flags == FLAG_NOT_DECODABLE {
+        return None;
+    }
+    Some(instruction)
+}
+
+fn wstring_to_string(s: &WString) -> String {
+    let bytes: Vec<u8> = s.p.iter().take(s.length as usize).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// The mnemonic and operand text distorm prints for an instruction.
+fn format(rip: u64, code: &[u8], instruction: &mut DInst) -> (String, String) {
+    let mut code_info = code_info(rip, code);
+    let mut format_info: DecodedInst = Default::default();
+
+    unsafe {
+        distorm_format64(
+                &mut code_info as *mut CodeInfo,
+                instruction as *mut DInst,
+                &mut format_info as *mut DecodedInst);
+    };
+
+    (wstring_to_string(&format_info.mnemonic), wstring_to_string(&format_info.operands))
+}
+
+fn register(reg: u8) -> Register {
+    let in_range = |first: RegisterType, count: u8| -> Option<u8> {
+        let first = first as u8;
+        if reg >= first && reg < first + count { Some(reg - first) } else { None }
+    };
+    let gpr = |num: u8, size: u8| Register::Gpr { num, size, high: false };
+    in_range(RegisterType::R_RAX, 16).map(|n| gpr(n, 8))
+        .or_else(|| in_range(RegisterType::R_EAX, 16).map(|n| gpr(n, 4)))
+        .or_else(|| in_range(RegisterType::R_AX, 16).map(|n| gpr(n, 2)))
+        .or_else(|| in_range(RegisterType::R_AL, 4).map(|n| gpr(n, 1)))
+        .or_else(|| in_range(RegisterType::R_AH, 4).map(|n| Register::Gpr { num: n, size: 1, high: true }))
+        .or_else(|| in_range(RegisterType::R_R8B, 8).map(|n| gpr(n + 8, 1)))
+        .or_else(|| in_range(RegisterType::R_SPL, 4).map(|n| gpr(n + 4, 1)))
+        .or_else(|| in_range(RegisterType::R_XMM0, 16).map(Register::Vector))
+        .or_else(|| in_range(RegisterType::R_YMM0, 16).map(Register::Vector))
+        .unwrap_or(Register::Other)
+}
+
+fn is_32bit_reg(reg: u8) -> bool {
+    (RegisterType::R_EAX as u8..=RegisterType::R_R15D as u8).contains(&reg)
+}
+
+// In 64-bit mode only FS and GS have a base; the others are flat. distorm marks
+// segments that weren't explicitly overridden with SEGMENT_DEFAULT.
+fn segment(instruction: &DInst) -> Option<Segment> {
+    if instruction.segment == RegisterType::R_NONE as u8 || instruction.segment & SEGMENT_DEFAULT != 0 {
+        return None;
+    }
+    match instruction.segment {
+        s if s == RegisterType::R_FS as u8 => Some(Segment::Fs),
+        s if s == RegisterType::R_GS as u8 => Some(Segment::Gs),
+        _ => None
+    }
+}
+
+// None for the vector-indexed (VSIB) operands of gathers, which access one
+// address per vector element rather than a single one.
+fn memory(rip: u64, instruction: &DInst, op: &Operand) -> Option<Memory> {
+    let disp = match instruction.disp_size {
+        0 => 0,
+        _ => instruction.disp
+    };
+    let mut mem = Memory {
+        base: None,
+        index: None,
+        scale: 1,
+        disp,
+        segment: segment(instruction),
+        addr32: false
+    };
+    match OperandType::from_u8(op.typ) {
+        Some(OperandType::O_SMEM) => {
+            match op.index {
+                // RIP-relative operands are relative to the end of the instruction.
+                reg if reg == RegisterType::R_RIP as u8 => mem.disp = rip.wrapping_add(instruction.size as u64).wrapping_add(disp),
+                reg => mem.base = Some(register(reg))
+            }
+            mem.addr32 = is_32bit_reg(op.index);
+        }
+        Some(OperandType::O_MEM) => {
+            if instruction.base != RegisterType::R_NONE as u8 {
+                mem.base = Some(register(instruction.base));
+            }
+            let index = register(op.index);
+            if let Register::Vector(_) = index {
+                return None;
+            }
+            mem.index = Some(index);
+            // distorm leaves the scale at 0 when it is 1.
+            if instruction.scale != 0 {
+                mem.scale = instruction.scale;
+            }
+            mem.addr32 = is_32bit_reg(op.index);
+        }
+        _ => { }
+    }
+    Some(mem)
+}
+
+impl Decoder for Distorm {
+    fn decode(&self, rip: u64, code: &[u8]) -> Option<Instruction> {
+        let mut instruction = decompose(rip, code)?;
+        let (text, _) = format(rip, code, &mut instruction);
+
+        // Prefixes are part of distorm's mnemonic.
+        let mut mnemonic = text.as_str();
+        let mut rep = false;
+        for prefix in ["LOCK ", "REP ", "REPZ ", "REPNZ ", "REPE ", "REPNE "] {
+            if let Some(rest) = mnemonic.strip_prefix(prefix) {
+                rep |= prefix != "LOCK ";
+                mnemonic = rest;
+            }
+        }
+        let mut mnemonic = mnemonic.to_string();
+
+        let mut operands = Vec::new();
+        for op in instruction.ops.iter() {
+            let kind = match OperandType::from_u8(op.typ)? {
+                OperandType::O_NONE => break,
+                OperandType::O_REG => OperandKind::Register(register(op.index)),
+                OperandType::O_IMM => OperandKind::Immediate(instruction.imm),
+                // ENTER's two immediates are packed into the halves of `imm`.
+                OperandType::O_IMM1 => OperandKind::Immediate(instruction.imm & 0xffffffff),
+                OperandType::O_IMM2 => OperandKind::Immediate(instruction.imm >> 32),
+                OperandType::O_DISP | OperandType::O_SMEM | OperandType::O_MEM =>
+                    memory(rip, &instruction, op).map_or(OperandKind::Other, OperandKind::Memory),
+                OperandType::O_PC | OperandType::O_PTR => OperandKind::Other
+            };
+            operands.push(decoder::Operand { kind, size: op.size / 8 });
+        }
+
+        // distorm prints string instructions with segment overrides in their long
+        // form, with operands; give them the short form like the others.
+        let mut string_segment = None;
+        if ["MOVS", "STOS", "LODS", "CMPS", "SCAS"].contains(&mnemonic.as_str()) {
+            string_segment = segment(&instruction);
+            let suffix = match operands.first().map(|op| op.size) {
+                Some(1) => "B",
+                Some(2) => "W",
+                Some(4) => "D",
+                _ => "Q"
+            };
+            mnemonic.push_str(suffix);
+            operands.clear();
+        }
+
+        Some(Instruction {
+            len: instruction.size as usize,
+            mnemonic,
+            rep,
+            segment: string_segment,
+            operands,
+            writes_flags: instruction.modified_flags_mask != 0
+        })
+    }
+
+    fn format(&self, rip: u64, code: &[u8]) -> Option<String> {
+        let mut instruction = decompose(rip, code)?;
+        let (mnemonic, operands) = format(rip, code, &mut instruction);
+        if operands.is_empty() {
+            Some(mnemonic)
+        } else {
+            Some(format!("{} {}", mnemonic, operands))
+        }
+    }
+}
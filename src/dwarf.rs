@@ -0,0 +1,387 @@
+use crate::elf::{Elf, Reader};
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+
+#[derive(Copy, Clone)]
+struct Row {
+    address: u64,
+    file: usize,
+    line: u64,
+    end_sequence: bool
+}
+
+/// The address to source line mapping of an object file, built from its
+/// .debug_line section. Addresses are the unrelocated ones in the file.
+pub struct LineTable {
+    files: Vec<String>,
+    rows: Vec<Row>
+}
+
+/// The value of a DWARF 5 directory or file entry attribute that we care about.
+enum FormValue<'a> {
+    Str(&'a str),
+    Num(u64),
+    Other
+}
+
+struct Sections<'a> {
+    line: &'a [u8],
+    str_: &'a [u8],
+    line_str: &'a [u8]
+}
+
+// Returns None for forms we don't know the size of, after which nothing more of
+// the header can be read.
+fn read_form<'a>(r: &mut Reader<'a>, form: u64, offset_size: usize, sections: &Sections<'a>) -> Option<FormValue<'a>> {
+    let read_offset = |r: &mut Reader| -> usize {
+        if offset_size == 8 { r.u64() as usize } else { r.u32() as usize }
+    };
+    let value = match form {
+        DW_FORM_STRING => FormValue::Str(r.cstr()),
+        DW_FORM_STRP => FormValue::Str(Reader::at(sections.str_, read_offset(r)).cstr()),
+        DW_FORM_LINE_STRP => FormValue::Str(Reader::at(sections.line_str, read_offset(r)).cstr()),
+        DW_FORM_UDATA => FormValue::Num(r.uleb128()),
+        DW_FORM_DATA1 => FormValue::Num(r.u8() as u64),
+        DW_FORM_DATA2 => FormValue::Num(r.u16() as u64),
+        DW_FORM_DATA4 => FormValue::Num(r.u32() as u64),
+        DW_FORM_DATA8 => FormValue::Num(r.u64()),
+        DW_FORM_DATA16 => { r.skip(16); FormValue::Other }
+        DW_FORM_BLOCK => { let len = r.uleb128() as usize; r.skip(len); FormValue::Other }
+        _ => return None
+    };
+    Some(value)
+}
+
+// DWARF 5 describes directory and file entries with a list of (content type, form)
+// pairs. Returns (path, directory index) for each entry.
+fn read_v5_entries<'a>(r: &mut Reader<'a>, offset_size: usize, sections: &Sections<'a>) -> Option<Vec<(&'a str, usize)>> {
+    let format_count = r.u8() as usize;
+    let mut format = Vec::with_capacity(format_count);
+    for _ in 0..format_count {
+        let content_type = r.uleb128();
+        let form = r.uleb128();
+        format.push((content_type, form));
+    }
+    let count = r.uleb128() as usize;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        if r.is_empty() {
+            return None;
+        }
+        let mut path = "";
+        let mut dir = 0;
+        for &(content_type, form) in &format {
+            match (content_type, read_form(r, form, offset_size, sections)?) {
+                (DW_LNCT_PATH, FormValue::Str(s)) => path = s,
+                (DW_LNCT_DIRECTORY_INDEX, FormValue::Num(n)) => dir = n as usize,
+                _ => { }
+            }
+        }
+        entries.push((path, dir));
+    }
+    Some(entries)
+}
+
+fn join(dir: &str, file: &str) -> String {
+    if file.starts_with('/') || dir.is_empty() {
+        file.to_string()
+    } else {
+        format!("{}/{}", dir, file)
+    }
+}
+
+impl LineTable {
+    pub fn new(elf: &Elf) -> LineTable {
+        let section_data = |name: &str| -> &[u8] {
+            match elf.section_by_name(name) {
+                Some(section) => elf.section_data(section),
+                None => &[]
+            }
+        };
+        let sections = Sections {
+            line: section_data(".debug_line"),
+            str_: section_data(".debug_str"),
+            line_str: section_data(".debug_line_str")
+        };
+        LineTable::parse(&sections)
+    }
+
+    fn parse(sections: &Sections) -> LineTable {
+        let mut table = LineTable { files: Vec::new(), rows: Vec::new() };
+        let mut r = Reader::new(sections.line);
+        while !r.is_empty() {
+            match table.read_unit(&mut r, sections) {
+                Some(next_unit) => r.pos = next_unit,
+                // Without a usable length there's no telling where the next unit
+                // starts.
+                None => break
+            }
+        }
+
+        table.rows.sort_by_key(|a| a.address);
+        table
+    }
+
+    // Parse one line number program, returning the offset of the next one.
+    fn read_unit<'a>(&mut self, r: &mut Reader<'a>, sections: &Sections<'a>) -> Option<usize> {
+        let mut unit_length = r.u32() as u64;
+        let mut offset_size = 4;
+        if unit_length == 0xffffffff {
+            unit_length = r.u64();
+            offset_size = 8;
+        }
+        let unit_end = r.pos.checked_add(usize::try_from(unit_length).ok()?)?;
+        // A malformed program only costs the rest of its own unit.
+        self.read_program(r, sections, offset_size, unit_end);
+        Some(unit_end)
+    }
+
+    fn read_program<'a>(&mut self, r: &mut Reader<'a>, sections: &Sections<'a>, offset_size: usize,
+                        unit_end: usize) -> Option<()> {
+        let version = r.u16();
+        if !(2..=5).contains(&version) {
+            return None;
+        }
+        if version >= 5 {
+            let _address_size = r.u8();
+            let _segment_selector_size = r.u8();
+        }
+        let header_length = if offset_size == 8 { r.u64() } else { r.u32() as u64 };
+        let program_start = r.pos.checked_add(usize::try_from(header_length).ok()?)?;
+        let min_inst_length = r.u8() as u64;
+        if version >= 4 {
+            let _max_ops_per_inst = r.u8();
+        }
+        let default_is_stmt = r.u8() != 0;
+        let line_base = r.u8() as i8 as i64;
+        let line_range = r.u8() as u64;
+        if line_range == 0 {
+            return None;
+        }
+        let opcode_base = r.u8();
+        let mut standard_opcode_lengths = Vec::new();
+        for _ in 1..opcode_base {
+            standard_opcode_lengths.push(r.u8());
+        }
+
+        // Map the unit's file numbers to indexes into self.files. Files are numbered
+        // from 1 before DWARF 5 and from 0 afterwards.
+        let mut files = Vec::new();
+        if version >= 5 {
+            let dirs = read_v5_entries(r, offset_size, sections)?;
+            for (path, dir) in read_v5_entries(r, offset_size, sections)? {
+                let dir = if dir < dirs.len() { dirs[dir].0 } else { "" };
+                files.push(self.add_file(join(dir, path)));
+            }
+        } else {
+            let mut dirs = vec![""];
+            loop {
+                let dir = r.cstr();
+                if dir.is_empty() { break; }
+                dirs.push(dir);
+            }
+            files.push(self.add_file("<unknown>".to_string()));
+            loop {
+                let path = r.cstr();
+                if path.is_empty() { break; }
+                let dir = r.uleb128() as usize;
+                let _mtime = r.uleb128();
+                let _length = r.uleb128();
+                let dir = if dir < dirs.len() { dirs[dir] } else { "" };
+                files.push(self.add_file(join(dir, path)));
+            }
+        }
+
+        if files.is_empty() {
+            files.push(self.add_file("<unknown>".to_string()));
+        }
+
+        r.pos = program_start;
+
+        let mut address = 0u64;
+        let mut file = 1;
+        let mut line = 1i64;
+        let _is_stmt = default_is_stmt;
+        while r.pos < unit_end && !r.is_empty() {
+            let opcode = r.u8();
+            if opcode >= opcode_base {
+                let adjusted = (opcode - opcode_base) as u64;
+                address = address.wrapping_add((adjusted / line_range).wrapping_mul(min_inst_length));
+                line = line.wrapping_add(line_base + (adjusted % line_range) as i64);
+                self.push_row(&files, address, file, line, false);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let len = r.uleb128() as usize;
+                    let end = r.pos.checked_add(len)?;
+                    match r.u8() {
+                        DW_LNE_END_SEQUENCE => {
+                            self.push_row(&files, address, file, line, true);
+                            address = 0;
+                            file = 1;
+                            line = 1;
+                        }
+                        DW_LNE_SET_ADDRESS => address = r.u64(),
+                        DW_LNE_DEFINE_FILE => {
+                            let path = r.cstr().to_string();
+                            files.push(self.add_file(path));
+                        }
+                        _ => { }
+                    }
+                    r.pos = end;
+                }
+                DW_LNS_COPY => self.push_row(&files, address, file, line, false),
+                DW_LNS_ADVANCE_PC => address = address.wrapping_add(r.uleb128().wrapping_mul(min_inst_length)),
+                DW_LNS_ADVANCE_LINE => line = line.wrapping_add(r.sleb128()),
+                DW_LNS_SET_FILE => file = r.uleb128() as usize,
+                DW_LNS_CONST_ADD_PC => {
+                    let adjusted = (255 - opcode_base) as u64;
+                    address = address.wrapping_add((adjusted / line_range).wrapping_mul(min_inst_length));
+                }
+                DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(r.u16() as u64),
+                _ => {
+                    // Skip the ULEB128 operands of any standard opcode we don't
+                    // need to interpret.
+                    let operands = *standard_opcode_lengths.get(opcode.checked_sub(1)? as usize)?;
+                    for _ in 0..operands {
+                        r.uleb128();
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn add_file(&mut self, path: String) -> usize {
+        self.files.push(path);
+        self.files.len() - 1
+    }
+
+    fn push_row(&mut self, files: &[usize], address: u64, file: usize, line: i64, end_sequence: bool) {
+        let file = if file < files.len() { files[file] } else { files[0] };
+        self.rows.push(Row {
+            address,
+            file,
+            line: line as u64,
+            end_sequence
+        });
+    }
+
+    /// The file and line that `address` was compiled from.
+    pub fn lookup(&self, address: u64) -> Option<(&str, u64)> {
+        // Find the last row at or before the address.
+        let (mut lo, mut hi) = (0, self.rows.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.rows[mid].address <= address {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo == 0 {
+            return None;
+        }
+        let row = &self.rows[lo - 1];
+        if row.end_sequence {
+            return None;
+        }
+        Some((&self.files[row.file], row.line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A DWARF 4 unit with one include directory and one file, and `program` as its
+    // line number program.
+    fn v4_unit(line_range: u8, program: &[u8]) -> Vec<u8> {
+        let mut header = vec![1, 1, 1, -5i8 as u8, line_range, 13];
+        header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        header.extend_from_slice(b"dir\0\0a.c\0\x01\0\0\0");
+        let mut unit = Vec::new();
+        unit.extend_from_slice(&4u16.to_le_bytes());
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend_from_slice(&header);
+        unit.extend_from_slice(program);
+        let mut out = (unit.len() as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(&unit);
+        out
+    }
+
+    // Set the address to 0x1000, go to line 5 and add a row, add a row 4 bytes and
+    // 1 line further on with a special opcode, then end the sequence 2 bytes later.
+    fn program() -> Vec<u8> {
+        let mut program = vec![0, 9, DW_LNE_SET_ADDRESS];
+        program.extend_from_slice(&0x1000u64.to_le_bytes());
+        program.extend_from_slice(&[DW_LNS_ADVANCE_LINE, 4, DW_LNS_COPY, 75]);
+        program.extend_from_slice(&[DW_LNS_ADVANCE_PC, 2, 0, 1, DW_LNE_END_SEQUENCE]);
+        program
+    }
+
+    fn parse(line: &[u8]) -> LineTable {
+        LineTable::parse(&Sections { line, str_: &[], line_str: &[] })
+    }
+
+    #[test]
+    fn lookup_rows() {
+        let table = parse(&v4_unit(14, &program()));
+        assert_eq!(table.lookup(0xfff), None);
+        assert_eq!(table.lookup(0x1000), Some(("dir/a.c", 5)));
+        assert_eq!(table.lookup(0x1005), Some(("dir/a.c", 6)));
+        assert_eq!(table.lookup(0x1006), None);
+    }
+
+    #[test]
+    fn zero_line_range_skips_the_unit() {
+        let mut section = v4_unit(0, &program());
+        section.extend_from_slice(&v4_unit(14, &program()));
+        let table = parse(&section);
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(table.lookup(0x1000), Some(("dir/a.c", 5)));
+    }
+
+    #[test]
+    fn oversized_unit_length() {
+        let mut section = vec![0xff; 12];
+        section.extend_from_slice(&v4_unit(14, &program()));
+        assert!(parse(&section).rows.is_empty());
+    }
+
+    #[test]
+    fn unknown_form_skips_the_unit() {
+        // Version 5 header whose directory entries use an undefined form.
+        let mut unit = 5u16.to_le_bytes().to_vec();
+        unit.extend_from_slice(&[8, 0, 0, 0, 0, 0, 1, 1, 1, -5i8 as u8, 14, 1]);
+        unit.extend_from_slice(&[1, DW_LNCT_PATH as u8, 0x7f, 1, 0]);
+        let mut section = (unit.len() as u32).to_le_bytes().to_vec();
+        section.extend_from_slice(&unit);
+        section.extend_from_slice(&v4_unit(14, &program()));
+        assert_eq!(parse(&section).lookup(0x1000), Some(("dir/a.c", 5)));
+    }
+}
@@ -0,0 +1,285 @@
+use std::cmp;
+use std::fs;
+use std::path::Path;
+use std::str;
+
+/// A little-endian cursor over a byte slice. Reads past the end return zero rather
+/// than failing; callers bound their loops by the lengths they were given so a
+/// truncated file just produces garbage-but-harmless results.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pub pos: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn at(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        let value = if self.pos < self.data.len() { self.data[self.pos] } else { 0 };
+        self.pos += 1;
+        value
+    }
+
+    fn usize_n(&mut self, bytes: usize) -> u64 {
+        let mut value = 0u64;
+        for i in 0..bytes {
+            value |= (self.u8() as u64) << (8 * i);
+        }
+        value
+    }
+
+    pub fn u16(&mut self) -> u16 { self.usize_n(2) as u16 }
+    pub fn u32(&mut self) -> u32 { self.usize_n(4) as u32 }
+    pub fn u64(&mut self) -> u64 { self.usize_n(8) }
+
+    pub fn skip(&mut self, bytes: usize) {
+        self.pos = self.pos.saturating_add(bytes);
+    }
+
+    pub fn uleb128(&mut self) -> u64 {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8();
+            if shift < 64 {
+                value |= ((byte & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 || self.is_empty() {
+                return value;
+            }
+        }
+    }
+
+    pub fn sleb128(&mut self) -> i64 {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8();
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 || self.is_empty() {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1i64 << shift;
+                }
+                return value;
+            }
+        }
+    }
+
+    /// A NUL terminated string.
+    pub fn cstr(&mut self) -> &'a str {
+        let start = cmp::min(self.pos, self.data.len());
+        let mut end = start;
+        while end < self.data.len() && self.data[end] != 0 {
+            end += 1;
+        }
+        self.pos = end + 1;
+        str::from_utf8(&self.data[start..end]).unwrap_or("")
+    }
+}
+
+pub const PT_LOAD: u32 = 1;
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_DYNSYM: u32 = 11;
+
+pub const SHF_COMPRESSED: u64 = 0x800;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+pub struct ProgramHeader {
+    pub typ: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64
+}
+
+pub struct SectionHeader {
+    pub name: String,
+    pub typ: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32
+}
+
+#[derive(Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64
+}
+
+/// Just enough of a 64-bit little-endian ELF file to find its segments, symbols
+/// and debug sections.
+pub struct Elf {
+    pub data: Vec<u8>,
+    pub program_headers: Vec<ProgramHeader>,
+    pub sections: Vec<SectionHeader>
+}
+
+impl Elf {
+    pub fn open(path: &Path) -> Option<Elf> {
+        fs::read(path).ok().and_then(Elf::parse)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Option<Elf> {
+        if data.len() < 64 || &data[..4] != b"\x7fELF" || data[4] != 2 || data[5] != 1 {
+            return None;
+        }
+
+        let (phoff, shoff, phentsize, phnum, shentsize, shnum, shstrndx) = {
+            let mut r = Reader::at(&data, 0x20);
+            let phoff = r.u64();
+            let shoff = r.u64();
+            r.skip(6);
+            let phentsize = r.u16() as usize;
+            let phnum = r.u16() as usize;
+            let shentsize = r.u16() as usize;
+            let shnum = r.u16() as usize;
+            let shstrndx = r.u16() as usize;
+            (phoff as usize, shoff as usize, phentsize, phnum, shentsize, shnum, shstrndx)
+        };
+
+        let mut program_headers = Vec::with_capacity(phnum);
+        for i in 0..phnum {
+            let mut r = Reader::at(&data, phoff + i * phentsize);
+            let typ = r.u32();
+            r.skip(4);
+            let offset = r.u64();
+            let vaddr = r.u64();
+            r.skip(8);
+            let filesz = r.u64();
+            let memsz = r.u64();
+            program_headers.push(ProgramHeader {
+                typ,
+                offset,
+                vaddr,
+                filesz,
+                memsz
+            });
+        }
+
+        let mut raw_sections = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            let mut r = Reader::at(&data, shoff + i * shentsize);
+            let name = r.u32();
+            let typ = r.u32();
+            let flags = r.u64();
+            let addr = r.u64();
+            let offset = r.u64();
+            let size = r.u64();
+            let link = r.u32();
+            raw_sections.push((name, typ, flags, addr, offset, size, link));
+        }
+
+        let mut sections = Vec::with_capacity(shnum);
+        for &(name, typ, flags, addr, offset, size, link) in &raw_sections {
+            let name = if shstrndx < raw_sections.len() {
+                let strtab_offset = raw_sections[shstrndx].4 as usize;
+                Reader::at(&data, strtab_offset + name as usize).cstr().to_string()
+            } else {
+                String::new()
+            };
+            sections.push(SectionHeader {
+                name,
+                typ,
+                flags,
+                addr,
+                offset,
+                size,
+                link
+            });
+        }
+
+        Some(Elf {
+            data,
+            program_headers,
+            sections
+        })
+    }
+
+    pub fn section_by_name(&self, name: &str) -> Option<&SectionHeader> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// The contents of a section. Compressed sections aren't supported and come
+    /// back empty.
+    pub fn section_data(&self, section: &SectionHeader) -> &[u8] {
+        let start = section.offset as usize;
+        let end = start + section.size as usize;
+        if section.flags & SHF_COMPRESSED != 0 || end > self.data.len() {
+            return &[];
+        }
+        &self.data[start..end]
+    }
+
+    /// The contents of the GNU build id note, if the file has one.
+    pub fn build_id(&self) -> Option<Vec<u8>> {
+        let section = self.section_by_name(".note.gnu.build-id")?;
+        let mut r = Reader::new(self.section_data(section));
+        let namesz = r.u32() as usize;
+        let descsz = r.u32() as usize;
+        let _typ = r.u32();
+        // The name ("GNU\0") is padded to a multiple of four bytes.
+        r.skip((namesz + 3) & !3);
+        let start = r.pos;
+        let data = self.section_data(section);
+        if start + descsz > data.len() {
+            return None;
+        }
+        Some(data[start..start + descsz].to_vec())
+    }
+
+    /// All function and object symbols from .symtab, or .dynsym if the file has
+    /// been stripped.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        let table = self.sections.iter().find(|s| s.typ == SHT_SYMTAB)
+            .or_else(|| self.sections.iter().find(|s| s.typ == SHT_DYNSYM));
+        let table = match table {
+            Some(table) => table,
+            None => return symbols
+        };
+        let strtab = match self.sections.get(table.link as usize) {
+            Some(strtab) => self.section_data(strtab),
+            None => return symbols
+        };
+        let data = self.section_data(table);
+        let count = data.len() / 24;
+        for i in 0..count {
+            let mut r = Reader::at(data, i * 24);
+            let name = r.u32();
+            let info = r.u8();
+            r.skip(3);
+            let value = r.u64();
+            let size = r.u64();
+            let typ = info & 0xf;
+            if (typ != STT_FUNC && typ != STT_OBJECT) || value == 0 {
+                continue;
+            }
+            symbols.push(Symbol {
+                name: Reader::at(strtab, name as usize).cstr().to_string(),
+                value,
+                size
+            });
+        }
+        symbols
+    }
+}
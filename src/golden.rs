@@ -0,0 +1,224 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::access::AccessKind;
+use crate::normalize::{Normalization, Region};
+use crate::tracer::Trace;
+
+/// The parts of a trace that a constant time target should keep from one build to
+/// the next. Addresses are reduced to offsets into their mapping (instructions) or
+/// to which buffer or mapping they fall in (memory accesses), and files are named
+/// without their directory, so the shape is the same wherever the binary was built.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Shape {
+    pub instructions: usize,
+    /// Hash of the sequence of instruction offsets.
+    pub rip_hash: u64,
+    pub mem_accesses: usize,
+    /// Hash of the kind, size and region of every memory access, and the step it
+    /// was made by.
+    pub mem_hash: u64
+}
+
+// 64-bit FNV-1a. The hashes end up in files that are checked in, so unlike the
+// standard library's hashers its output mustn't change between Rust releases.
+// Values are fed in as fixed size little endian integers for the same reason.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    // Length prefixed, so that consecutive strings can't run into each other.
+    fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.bytes(s.as_bytes());
+    }
+}
+
+// The file name of a region, without any directory.
+fn region_name(region: &Region) -> String {
+    match *region {
+        Region::Mapping(ref name) => match name.rfind('/') {
+            Some(i) => name[i + 1..].to_string(),
+            None => name.clone()
+        },
+        ref region => format!("{}", region)
+    }
+}
+
+impl Shape {
+    pub fn of(trace: &Trace) -> Shape {
+        let space = trace.address_space();
+
+        let mut rips = Fnv1a::new();
+        for step in trace.steps.iter() {
+            let (region, offset) = space.normalize(step.rip, Normalization::Mapping);
+            rips.str(&region_name(&region));
+            rips.u64(offset);
+        }
+
+        let mut mem = Fnv1a::new();
+        let mut mem_accesses = 0usize;
+        for (i, step) in trace.steps.iter().enumerate() {
+            for access in step.mem_accesses.iter() {
+                let (region, _) = space.normalize(access.addr, Normalization::Allocation);
+                mem.u64(i as u64);
+                mem.u64(match access.kind {
+                    AccessKind::Load => 0,
+                    AccessKind::Store => 1,
+                    AccessKind::LoadStore => 2
+                });
+                mem.u64(access.size as u64);
+                mem.str(&region_name(&region));
+                mem_accesses += 1;
+            }
+        }
+
+        Shape {
+            instructions: trace.steps.len(),
+            rip_hash: rips.0,
+            mem_accesses,
+            mem_hash: mem.0
+        }
+    }
+
+    /// Describe how `other` differs from this shape.
+    pub fn differences(&self, other: &Shape) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.instructions != other.instructions {
+            differences.push(format!("{} instructions instead of {}", other.instructions, self.instructions));
+        } else if self.rip_hash != other.rip_hash {
+            differences.push("different instructions".to_string());
+        }
+        if self.mem_accesses != other.mem_accesses {
+            differences.push(format!("{} memory accesses instead of {}", other.mem_accesses, self.mem_accesses));
+        } else if self.mem_hash != other.mem_hash {
+            differences.push("different memory accesses".to_string());
+        }
+        differences
+    }
+}
+
+fn invalid(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid golden file: {}", detail))
+}
+
+/// Read a shape written by `write`. Returns None if the file doesn't exist yet.
+pub fn read(path: &Path) -> io::Result<Option<Shape>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    };
+
+    let mut shape = Shape { instructions: 0, rip_hash: 0, mem_accesses: 0, mem_hash: 0 };
+    let mut seen = 0usize;
+    for line in text.lines() {
+        let mut fields = line.split(' ');
+        let (key, value) = match (fields.next(), fields.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue
+        };
+        let ok = match key {
+            "instructions" => value.parse().map(|n| shape.instructions = n).is_ok(),
+            "rip-hash" => u64::from_str_radix(value, 16).map(|h| shape.rip_hash = h).is_ok(),
+            "memory-accesses" => value.parse().map(|n| shape.mem_accesses = n).is_ok(),
+            "memory-hash" => u64::from_str_radix(value, 16).map(|h| shape.mem_hash = h).is_ok(),
+            _ => continue
+        };
+        if !ok {
+            return Err(invalid(format!("bad value for {}: {}", key, value)));
+        }
+        seen += 1;
+    }
+    if seen != 4 {
+        return Err(invalid("missing fields".to_string()));
+    }
+    Ok(Some(shape))
+}
+
+/// Write `shape` to `path` as text, so changes to it show up readably in a diff.
+pub fn write(path: &Path, shape: &Shape) -> io::Result<()> {
+    let text = format!("instructions {}\nrip-hash {:016x}\nmemory-accesses {}\nmemory-hash {:016x}\n",
+                       shape.instructions, shape.rip_hash, shape.mem_accesses, shape.mem_hash);
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::MemAccess;
+    use crate::inputs::InputClass;
+    use crate::maps::Mapping;
+    use crate::tracer::Step;
+
+    #[test]
+    fn fnv1a_test_vectors() {
+        for (input, expected) in [("", 0xcbf29ce484222325), ("a", 0xaf63dc4c8601ec8c),
+                                  ("foobar", 0x85944171f73967e8)] {
+            let mut hash = Fnv1a::new();
+            hash.bytes(input.as_bytes());
+            assert_eq!(hash.0, expected, "{:?}", input);
+        }
+    }
+
+    fn trace(base: u64) -> Trace {
+        let step = |offset: u64, mem_accesses: Vec<MemAccess>| {
+            Step { rip: base + offset, code: [0x90; 15], code_len: 1, mem_accesses }
+        };
+        let load = MemAccess { addr: 0x7ffc0010, size: 8, kind: AccessKind::Load };
+        Trace {
+            class: InputClass::Random,
+            inputs: Vec::new(),
+            input_addrs: Vec::new(),
+            inputs_addr: 0,
+            steps: vec![step(0x10, vec![load]), step(0x11, Vec::new()), step(0x10, vec![load])],
+            maps: vec![
+                Mapping { start: base, end: base + 0x1000, perms: "r-xp".to_string(), offset: 0,
+                          path: Some(format!("/build/{:x}/target", base)) },
+                Mapping { start: 0x7ffc0000, end: 0x7ffc1000, perms: "rw-p".to_string(), offset: 0,
+                          path: Some("[stack]".to_string()) }
+            ]
+        }
+    }
+
+    #[test]
+    fn shape_ignores_load_address_and_directory() {
+        let shape = Shape::of(&trace(0x400000));
+        assert_eq!(shape.instructions, 3);
+        assert_eq!(shape.mem_accesses, 2);
+        assert_eq!(Shape::of(&trace(0x555555554000)), shape);
+    }
+
+    #[test]
+    fn write_read_compare() {
+        let shape = Shape::of(&trace(0x400000));
+        let path = std::env::temp_dir().join(format!("fixed-time-tester-{}.golden", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(read(&path).unwrap(), None);
+        write(&path, &shape).unwrap();
+        let read_shape = read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_shape, Some(shape));
+        assert!(shape.differences(&read_shape.unwrap()).is_empty());
+
+        let mut other = trace(0x400000);
+        other.steps.pop();
+        let differences = shape.differences(&Shape::of(&other));
+        assert_eq!(differences, vec!["2 instructions instead of 3", "1 memory accesses instead of 2"]);
+    }
+}
@@ -0,0 +1,137 @@
+use iced_x86::{Formatter, IntelFormatter, OpKind};
+
+use crate::decoder::{Decoder, Instruction, Memory, Operand, OperandKind, Register, Segment};
+
+/// Decodes with iced-x86, which is pure Rust and needs nothing installed.
+pub struct Iced;
+
+fn register(reg: iced_x86::Register) -> Register {
+    if reg.is_gpr() {
+        let high = matches!(reg, iced_x86::Register::AH | iced_x86::Register::CH |
+                                 iced_x86::Register::DH | iced_x86::Register::BH);
+        Register::Gpr { num: reg.full_register().number() as u8, size: reg.size() as u8, high }
+    } else if reg.is_vector_register() {
+        Register::Vector(reg.number() as u8)
+    } else {
+        Register::Other
+    }
+}
+
+fn segment(instruction: &iced_x86::Instruction) -> Option<Segment> {
+    match instruction.memory_segment() {
+        iced_x86::Register::FS => Some(Segment::Fs),
+        iced_x86::Register::GS => Some(Segment::Gs),
+        _ => None
+    }
+}
+
+// None for the vector-indexed (VSIB) operands of gathers and scatters, which
+// access one address per vector element rather than a single one.
+fn memory(instruction: &iced_x86::Instruction) -> Option<Memory> {
+    let base = instruction.memory_base();
+    let index = instruction.memory_index();
+    if index.is_vector_register() {
+        return None;
+    }
+    let segment = segment(instruction);
+    let addr32 = base.is_gpr32() || index.is_gpr32() || base == iced_x86::Register::EIP;
+    // iced-x86 already gives the target of a RIP-relative operand as the
+    // displacement.
+    let base = match base {
+        iced_x86::Register::None | iced_x86::Register::RIP | iced_x86::Register::EIP => None,
+        reg => Some(register(reg))
+    };
+    let index = match index {
+        iced_x86::Register::None => None,
+        reg => Some(register(reg))
+    };
+    Some(Memory {
+        base,
+        index,
+        scale: instruction.memory_index_scale() as u8,
+        disp: instruction.memory_displacement64(),
+        segment,
+        addr32
+    })
+}
+
+// A memory operand addressed by general purpose register `num` alone, which is
+// how iced describes MASKMOVDQU's store to [RDI].
+fn register_memory(instruction: &iced_x86::Instruction, num: u8, addr32: bool) -> Memory {
+    Memory {
+        base: Some(Register::Gpr { num, size: if addr32 { 4 } else { 8 }, high: false }),
+        index: None,
+        scale: 1,
+        disp: 0,
+        segment: segment(instruction),
+        addr32
+    }
+}
+
+fn decode_one(rip: u64, code: &[u8]) -> Option<iced_x86::Instruction> {
+    let mut decoder = iced_x86::Decoder::with_ip(64, code, rip, iced_x86::DecoderOptions::NONE);
+    let instruction = decoder.decode();
+    if instruction.is_invalid() {
+        None
+    } else {
+        Some(instruction)
+    }
+}
+
+impl Decoder for Iced {
+    fn decode(&self, rip: u64, code: &[u8]) -> Option<Instruction> {
+        let instruction = decode_one(rip, code)?;
+        let memory_size = instruction.memory_size().size() as u16;
+
+        // The RSI and RDI operands of a string instruction are left out, since it
+        // is described by its mnemonic alone.
+        let op_count = if instruction.is_string_instruction() { 0 } else { instruction.op_count() };
+        let mut operands = Vec::new();
+        for i in 0..op_count {
+            let (kind, size) = match instruction.op_kind(i) {
+                OpKind::Register => {
+                    let reg = instruction.op_register(i);
+                    (OperandKind::Register(register(reg)), reg.size() as u16)
+                }
+                OpKind::Memory => match memory(&instruction) {
+                    Some(mem) => (OperandKind::Memory(mem), memory_size),
+                    None => (OperandKind::Other, 0)
+                },
+                OpKind::Immediate8 | OpKind::Immediate8_2nd => (OperandKind::Immediate(instruction.immediate(i)), 1),
+                OpKind::Immediate16 | OpKind::Immediate8to16 => (OperandKind::Immediate(instruction.immediate(i)), 2),
+                OpKind::Immediate32 | OpKind::Immediate8to32 => (OperandKind::Immediate(instruction.immediate(i)), 4),
+                OpKind::Immediate64 | OpKind::Immediate8to64 | OpKind::Immediate32to64 =>
+                    (OperandKind::Immediate(instruction.immediate(i)), 8),
+                OpKind::MemorySegRSI => (OperandKind::Memory(register_memory(&instruction, 6, false)), memory_size),
+                OpKind::MemorySegESI => (OperandKind::Memory(register_memory(&instruction, 6, true)), memory_size),
+                OpKind::MemorySegRDI | OpKind::MemoryESRDI =>
+                    (OperandKind::Memory(register_memory(&instruction, 7, false)), memory_size),
+                OpKind::MemorySegEDI | OpKind::MemoryESEDI =>
+                    (OperandKind::Memory(register_memory(&instruction, 7, true)), memory_size),
+                _ => (OperandKind::Other, 0)
+            };
+            operands.push(Operand { kind, size });
+        }
+
+        Some(Instruction {
+            len: instruction.len(),
+            mnemonic: format!("{:?}", instruction.mnemonic()).to_ascii_uppercase(),
+            rep: instruction.has_rep_prefix() || instruction.has_repne_prefix(),
+            segment: if instruction.is_string_instruction() { segment(&instruction) } else { None },
+            operands,
+            writes_flags: instruction.rflags_modified() != 0
+        })
+    }
+
+    fn format(&self, rip: u64, code: &[u8]) -> Option<String> {
+        let instruction = decode_one(rip, code)?;
+        let mut formatter = IntelFormatter::new();
+        formatter.options_mut().set_uppercase_all(true);
+        formatter.options_mut().set_hex_prefix("0x");
+        formatter.options_mut().set_hex_suffix("");
+        formatter.options_mut().set_space_after_operand_separator(true);
+        let mut text = String::new();
+        formatter.format(&instruction, &mut text);
+        Some(text)
+    }
+}
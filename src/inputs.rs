@@ -0,0 +1,255 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::ConstantTimeTarget;
+use crate::Config;
+
+/// A strategy for choosing the inputs of successive runs. Apart from `Random`,
+/// each one takes the shape (number and length of buffers) of the target's own
+/// random inputs and fills them with something more likely to provoke a secret
+/// dependent branch.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InputClass {
+    /// Whatever the target's `generate_inputs` produces.
+    Random,
+    /// One fixed input alternating with random ones.
+    FixedVsRandom,
+    /// Two fixed inputs alternating.
+    FixedVsFixed,
+    /// Every byte zero, alternating with random inputs.
+    AllZero,
+    /// Every byte 0xff, alternating with random inputs.
+    AllOnes,
+    /// Zero apart from one or two random bits per buffer.
+    LowHammingWeight,
+    /// One fixed input, then that input with a single random bit flipped.
+    SingleBitFlip,
+    /// Buffers filled with boundary byte patterns (0x00, 0xff, 0x80, 0x7f, ...).
+    EdgeValues
+}
+
+static ALL: &[InputClass] = &[
+    InputClass::Random,
+    InputClass::FixedVsRandom,
+    InputClass::FixedVsFixed,
+    InputClass::AllZero,
+    InputClass::AllOnes,
+    InputClass::LowHammingWeight,
+    InputClass::SingleBitFlip,
+    InputClass::EdgeValues
+];
+
+// Byte patterns used by EdgeValues: (first byte, rest of the bytes).
+static EDGE_PATTERNS: &[(u8, u8)] = &[
+    (0x00, 0x00), (0xff, 0xff), (0x80, 0x00), (0x7f, 0xff), (0x01, 0x00),
+    (0xfe, 0xff), (0x00, 0x80), (0xff, 0x7f), (0x55, 0x55), (0xaa, 0xaa)
+];
+
+impl InputClass {
+    pub fn all() -> Vec<InputClass> {
+        ALL.to_vec()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            InputClass::Random => "random",
+            InputClass::FixedVsRandom => "fixed-vs-random",
+            InputClass::FixedVsFixed => "fixed-vs-fixed",
+            InputClass::AllZero => "zero",
+            InputClass::AllOnes => "ones",
+            InputClass::LowHammingWeight => "low-weight",
+            InputClass::SingleBitFlip => "bit-flip",
+            InputClass::EdgeValues => "edge"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<InputClass> {
+        ALL.iter().find(|c| c.name() == s).copied()
+    }
+}
+
+/// The input classes to run `target` with: the ones it opts into, restricted to
+/// those selected in `config` if any were.
+pub fn selected_classes(target: &dyn ConstantTimeTarget, config: &Config) -> Vec<InputClass> {
+    let classes = target.input_classes();
+    match config.input_classes {
+        Some(ref selected) => classes.into_iter().filter(|c| selected.contains(c)).collect(),
+        None => classes
+    }
+}
+
+fn filled(shape: &[Vec<u8>], value: u8) -> Vec<Vec<u8>> {
+    shape.iter().map(|buf| vec![value; buf.len()]).collect()
+}
+
+fn flip_random_bit(inputs: &mut [Vec<u8>], rng: &mut StdRng) {
+    let candidates: Vec<usize> = (0..inputs.len()).filter(|&i| !inputs[i].is_empty()).collect();
+    if candidates.is_empty() {
+        return;
+    }
+    let buf = &mut inputs[candidates[rng.gen_range(0..candidates.len())]];
+    let byte = rng.gen_range(0..buf.len());
+    buf[byte] ^= 1 << rng.gen_range(0..8);
+}
+
+/// Produces the inputs for successive runs of one class.
+pub struct InputGenerator {
+    class: InputClass,
+    count: usize,
+    fixed: Vec<Vec<Vec<u8>>>
+}
+
+impl InputGenerator {
+    pub fn new(class: InputClass) -> InputGenerator {
+        InputGenerator { class, count: 0, fixed: Vec::new() }
+    }
+
+    pub fn next(&mut self, target: &mut dyn ConstantTimeTarget, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        let k = self.count;
+        self.count += 1;
+
+        let random = target.generate_inputs(rng);
+        match self.class {
+            InputClass::Random => random,
+            InputClass::FixedVsRandom | InputClass::FixedVsFixed | InputClass::SingleBitFlip => {
+                let wanted = if self.class == InputClass::FixedVsFixed { 2 } else { 1 };
+                while self.fixed.len() < wanted {
+                    self.fixed.push(target.generate_inputs(rng));
+                }
+                match self.class {
+                    InputClass::FixedVsRandom if k % 2 == 1 => random,
+                    InputClass::SingleBitFlip if k > 0 => {
+                        let mut inputs = self.fixed[0].clone();
+                        flip_random_bit(&mut inputs, rng);
+                        inputs
+                    }
+                    _ => self.fixed[k % wanted].clone()
+                }
+            }
+            InputClass::AllZero | InputClass::AllOnes => {
+                if k % 2 == 1 {
+                    random
+                } else {
+                    filled(&random, if self.class == InputClass::AllZero { 0x00 } else { 0xff })
+                }
+            }
+            InputClass::LowHammingWeight => {
+                let mut inputs = filled(&random, 0);
+                for buf in inputs.iter_mut().filter(|b| !b.is_empty()) {
+                    for _ in 0..rng.gen_range(1..3) {
+                        let byte = rng.gen_range(0..buf.len());
+                        buf[byte] |= 1 << rng.gen_range(0..8);
+                    }
+                }
+                inputs
+            }
+            InputClass::EdgeValues => {
+                let (first, rest) = EDGE_PATTERNS[k % EDGE_PATTERNS.len()];
+                let mut inputs = filled(&random, rest);
+                for buf in inputs.iter_mut().filter(|b| !b.is_empty()) {
+                    buf[0] = first;
+                }
+                inputs
+            }
+        }
+    }
+}
+
+/// Storage that the inputs of every run are copied into before being handed to
+/// `run`. Freshly generated inputs land wherever the allocator puts them, which
+/// would show up as a difference in every memory access to them; keeping the
+/// buffers (and the slice of them `run` gets) at the same addresses avoids that.
+#[derive(Default)]
+pub struct InputBuffers {
+    buffers: Vec<Vec<u8>>
+}
+
+impl InputBuffers {
+    pub fn new() -> InputBuffers {
+        InputBuffers::default()
+    }
+
+    /// Copy `inputs` into the buffers. Their storage is only reallocated if an
+    /// input is longer than it was in an earlier run.
+    pub fn fill(&mut self, inputs: &[Vec<u8>]) -> &[Vec<u8>] {
+        if self.buffers.len() < inputs.len() {
+            self.buffers.resize_with(inputs.len(), Vec::new);
+        }
+        for (buf, input) in self.buffers.iter_mut().zip(inputs.iter()) {
+            buf.clear();
+            buf.extend_from_slice(input);
+        }
+        &self.buffers[..inputs.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngCore, SeedableRng};
+
+    struct TwoBuffers;
+
+    impl ConstantTimeTarget for TwoBuffers {
+        fn name(&self) -> &str { "two-buffers" }
+
+        fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>> {
+            let mut inputs = vec![vec![0u8; 16], vec![0u8; 4]];
+            for buf in inputs.iter_mut() {
+                rng.fill_bytes(buf);
+            }
+            inputs
+        }
+
+        fn run(&mut self, _inputs: &[Vec<u8>]) { }
+    }
+
+    fn generate(class: InputClass, runs: usize) -> Vec<Vec<Vec<u8>>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut generator = InputGenerator::new(class);
+        (0..runs).map(|_| generator.next(&mut TwoBuffers, &mut rng)).collect()
+    }
+
+    #[test]
+    fn fixed_vs_fixed_alternates_two_inputs() {
+        let runs = generate(InputClass::FixedVsFixed, 6);
+        assert_ne!(runs[0], runs[1]);
+        for k in 2..runs.len() {
+            assert_eq!(runs[k], runs[k % 2]);
+        }
+        assert_eq!(runs, generate(InputClass::FixedVsFixed, 6));
+    }
+
+    #[test]
+    fn zero_and_ones_alternate_with_random() {
+        for (class, value) in [(InputClass::AllZero, 0x00), (InputClass::AllOnes, 0xff)] {
+            let runs = generate(class, 4);
+            for (k, inputs) in runs.iter().enumerate() {
+                assert_eq!(inputs.iter().map(|b| b.len()).collect::<Vec<_>>(), vec![16, 4]);
+                let filled = inputs.iter().flatten().all(|&b| b == value);
+                assert_eq!(filled, k % 2 == 0, "{} run {}", class.name(), k);
+            }
+        }
+    }
+
+    #[test]
+    fn low_weight_sets_one_or_two_bits_per_buffer() {
+        for inputs in generate(InputClass::LowHammingWeight, 32) {
+            for buf in inputs.iter() {
+                let bits: u32 = buf.iter().map(|b| b.count_ones()).sum();
+                assert!((1..=2).contains(&bits), "{} bits set", bits);
+            }
+        }
+    }
+
+    #[test]
+    fn bit_flip_is_one_bit_from_the_first_run() {
+        let runs = generate(InputClass::SingleBitFlip, 16);
+        for inputs in runs.iter().skip(1) {
+            let distance: u32 = runs[0].iter().flatten().zip(inputs.iter().flatten())
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum();
+            assert_eq!(distance, 1);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::symbols::Symbolizer;
+
+// Division and square root, whose latency depends on their operands on every
+// x86-64 implementation.
+static DEFAULT: &[&str] = &[
+    "DIV", "IDIV",
+    "DIVSS", "DIVSD", "DIVPS", "DIVPD", "VDIVSS", "VDIVSD", "VDIVPS", "VDIVPD",
+    "SQRTSS", "SQRTSD", "SQRTPS", "SQRTPD", "VSQRTSS", "VSQRTSD", "VSQRTPS", "VSQRTPD",
+    "FDIV", "FDIVP", "FIDIV", "FDIVR", "FDIVRP", "FIDIVR", "FSQRT"
+];
+
+// Instructions that are only variable latency on some microarchitectures (64-bit
+// multiplies on low power cores) or for some operand classes (denormal floating
+// point inputs and results take a microcode assist).
+static STRICT: &[&str] = &[
+    "MUL", "IMUL", "MULX",
+    "ADDSS", "ADDSD", "ADDPS", "ADDPD", "VADDSS", "VADDSD", "VADDPS", "VADDPD",
+    "SUBSS", "SUBSD", "SUBPS", "SUBPD", "VSUBSS", "VSUBSD", "VSUBPS", "VSUBPD",
+    "MULSS", "MULSD", "MULPS", "MULPD", "VMULSS", "VMULSD", "VMULPS", "VMULPD",
+    "RCPSS", "RCPPS", "RSQRTSS", "RSQRTPS",
+    "CVTSD2SS", "CVTSS2SD", "CVTPD2PS", "CVTPS2PD",
+    "FADD", "FADDP", "FIADD", "FSUB", "FSUBP", "FISUB", "FMUL", "FMULP", "FIMUL"
+];
+
+/// The set of (upper case, Intel) mnemonics considered to have data dependent
+/// latency.
+#[derive(Clone, Debug)]
+pub struct LatencyPolicy {
+    mnemonics: Vec<String>
+}
+
+impl Default for LatencyPolicy {
+    /// Division and square root.
+    fn default() -> LatencyPolicy {
+        LatencyPolicy { mnemonics: DEFAULT.iter().map(|s| s.to_string()).collect() }
+    }
+}
+
+impl LatencyPolicy {
+    /// The default policy plus multiplies and denormal sensitive floating point.
+    pub fn strict() -> LatencyPolicy {
+        let mut policy = LatencyPolicy::default();
+        policy.mnemonics.extend(STRICT.iter().map(|s| s.to_string()));
+        policy
+    }
+
+    /// Parse a comma separated list of mnemonics. The names `default` and `strict`
+    /// stand for the builtin lists, so `default,PMULUDQ` extends the default.
+    pub fn parse(s: &str) -> Option<LatencyPolicy> {
+        let mut policy = LatencyPolicy { mnemonics: Vec::new() };
+        for entry in s.split(',') {
+            match entry.trim() {
+                "" => return None,
+                "default" => policy.mnemonics.extend(DEFAULT.iter().map(|s| s.to_string())),
+                "strict" => {
+                    policy.mnemonics.extend(DEFAULT.iter().map(|s| s.to_string()));
+                    policy.mnemonics.extend(STRICT.iter().map(|s| s.to_string()));
+                }
+                mnemonic => policy.mnemonics.push(mnemonic.to_ascii_uppercase())
+            }
+        }
+        Some(policy)
+    }
+
+    pub fn is_variable(&self, mnemonic: &str) -> bool {
+        self.mnemonics.iter().any(|m| m == mnemonic)
+    }
+}
+
+/// Variable latency instructions seen so far, by address.
+#[derive(Default)]
+pub struct Findings {
+    seen: HashMap<u64, (String, usize)>
+}
+
+impl Findings {
+    pub fn new() -> Findings {
+        Findings { seen: HashMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Record an execution of `mnemonic` at `rip`. Returns true the first time a
+    /// given instruction is seen.
+    pub fn record(&mut self, rip: u64, mnemonic: &str) -> bool {
+        if let Some(entry) = self.seen.get_mut(&rip) {
+            entry.1 += 1;
+            return false;
+        }
+        self.seen.insert(rip, (mnemonic.to_string(), 1));
+        true
+    }
+
+    pub fn print(&self, symbols: &Symbolizer) {
+        let mut rips: Vec<&u64> = self.seen.keys().collect();
+        rips.sort();
+        println!("Variable latency instructions executed:");
+        for rip in rips {
+            let (ref mnemonic, count) = self.seen[rip];
+            println!("    {:016x} {:<8} x{:<6} {}", *rip, mnemonic, count, symbols.describe(*rip));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_lists() {
+        let policy = LatencyPolicy::parse("default").unwrap();
+        assert!(policy.is_variable("DIV") && policy.is_variable("VSQRTPD"));
+        assert!(!policy.is_variable("MUL"));
+        let policy = LatencyPolicy::parse("strict").unwrap();
+        assert!(policy.is_variable("DIV") && policy.is_variable("MUL") && policy.is_variable("FMULP"));
+        assert!(!policy.is_variable("PMULUDQ"));
+    }
+
+    #[test]
+    fn parse_mnemonics() {
+        let policy = LatencyPolicy::parse("default, pmuludq").unwrap();
+        assert!(policy.is_variable("DIV") && policy.is_variable("PMULUDQ"));
+        let policy = LatencyPolicy::parse("mul,Imul").unwrap();
+        assert!(policy.is_variable("MUL") && policy.is_variable("IMUL"));
+        assert!(!policy.is_variable("DIV") && !policy.is_variable("mul"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in ["", " ", ",", "DIV,", ",DIV", "DIV,,MUL", "default, ,MUL"] {
+            assert!(LatencyPolicy::parse(s).is_none(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::access::MemAccess;
+
+/// How much of a memory access an attacker is assumed to be able to observe.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LeakageModel {
+    /// The exact address, width and direction of every access.
+    Exact,
+    /// Which 64 byte cache lines are touched, as seen by a cache-timing attacker
+    /// that can distinguish individual lines (e.g. Flush+Reload).
+    CacheLine,
+    /// Which 4 KiB pages are touched, as seen by a controlled-channel attacker
+    /// watching page faults or page table accessed bits.
+    Page,
+    /// Which cache sets are touched, as seen by a Prime+Probe attacker. The
+    /// geometry is the line size in bytes and the number of sets.
+    CacheSet { line_size: u64, sets: u64 }
+}
+
+pub const CACHE_LINE_SIZE: u64 = 64;
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The geometry of a typical 32 KiB, 8-way L1 data cache.
+pub const DEFAULT_CACHE_SETS: u64 = 64;
+
+impl LeakageModel {
+    /// The first and last unit of observation an access touches. An unaligned
+    /// access can straddle two lines or pages.
+    pub fn observe(&self, access: &MemAccess) -> (u64, u64) {
+        let last_byte = access.addr + (if access.size > 0 { access.size as u64 - 1 } else { 0 });
+        match *self {
+            LeakageModel::Exact => (access.addr, last_byte),
+            LeakageModel::CacheLine => (access.addr / CACHE_LINE_SIZE, last_byte / CACHE_LINE_SIZE),
+            LeakageModel::Page => (access.addr / PAGE_SIZE, last_byte / PAGE_SIZE),
+            LeakageModel::CacheSet { line_size, sets } => {
+                ((access.addr / line_size) % sets, (last_byte / line_size) % sets)
+            }
+        }
+    }
+
+    /// Whether an attacker under this model can tell the two accesses apart.
+    pub fn same(&self, a: &MemAccess, b: &MemAccess) -> bool {
+        match *self {
+            LeakageModel::Exact => a == b,
+            _ => self.observe(a) == self.observe(b)
+        }
+    }
+
+    /// What the attacker sees of an access, for reports.
+    pub fn describe(&self, access: &MemAccess) -> String {
+        let (first, last) = self.observe(access);
+        let unit = match *self {
+            LeakageModel::Exact => return format!("{}", access),
+            LeakageModel::CacheLine => "line",
+            LeakageModel::Page => "page",
+            LeakageModel::CacheSet { .. } => "set"
+        };
+        if first == last {
+            format!("{} {:x}", unit, first)
+        } else {
+            format!("{}s {:x}-{:x}", unit, first, last)
+        }
+    }
+
+    /// Parse a model name as accepted on the command line: `exact`, `line`, `page`,
+    /// or `set[:LINE_SIZE:SETS]`.
+    pub fn parse(s: &str) -> Option<LeakageModel> {
+        match s {
+            "exact" => return Some(LeakageModel::Exact),
+            "line" => return Some(LeakageModel::CacheLine),
+            "page" => return Some(LeakageModel::Page),
+            "set" => return Some(LeakageModel::CacheSet {
+                line_size: CACHE_LINE_SIZE,
+                sets: DEFAULT_CACHE_SETS
+            }),
+            _ => { }
+        }
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 || parts[0] != "set" {
+            return None;
+        }
+        match (parts[1].parse(), parts[2].parse()) {
+            (Ok(line_size), Ok(sets)) if line_size > 0 && sets > 0 => {
+                Some(LeakageModel::CacheSet { line_size, sets })
+            }
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessKind;
+
+    #[test]
+    fn parse_names() {
+        assert_eq!(LeakageModel::parse("exact"), Some(LeakageModel::Exact));
+        assert_eq!(LeakageModel::parse("line"), Some(LeakageModel::CacheLine));
+        assert_eq!(LeakageModel::parse("page"), Some(LeakageModel::Page));
+        assert_eq!(LeakageModel::parse("set"),
+                   Some(LeakageModel::CacheSet { line_size: CACHE_LINE_SIZE, sets: DEFAULT_CACHE_SETS }));
+    }
+
+    #[test]
+    fn parse_set_geometry() {
+        assert_eq!(LeakageModel::parse("set:128:1024"),
+                   Some(LeakageModel::CacheSet { line_size: 128, sets: 1024 }));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in ["", "lines", "Exact", "set:", "set:64", "set:64:", "set:0:64", "set:64:0",
+                  "set:x:64", "set:64:-1", "set:64:64:64", "line:64:64"] {
+            assert_eq!(LeakageModel::parse(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn describe_access() {
+        let access = MemAccess { addr: 0x1030, size: 8, kind: AccessKind::Load };
+        assert_eq!(LeakageModel::Exact.describe(&access), "load 8 @ 1030");
+        assert_eq!(LeakageModel::CacheLine.describe(&access), "line 40");
+        let access = MemAccess { addr: 0x13c, size: 8, kind: AccessKind::Load };
+        assert_eq!(LeakageModel::CacheLine.describe(&access), "lines 4-5");
+        assert_eq!(LeakageModel::parse("set:64:4").unwrap().describe(&access), "sets 0-1");
+    }
+}
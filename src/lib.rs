@@ -0,0 +1,149 @@
+use std::path::PathBuf;
+
+use rand::rngs::StdRng;
+
+use leakage::LeakageModel;
+use normalize::Normalization;
+use latency::LatencyPolicy;
+use inputs::InputClass;
+
+pub use tracer::{trace_target, trace_process, trace_program};
+pub use timing::timing_test;
+
+pub mod sys;
+pub mod ptrace;
+pub mod decoder;
+pub mod iced;
+#[cfg(feature = "distorm")]
+pub mod distorm;
+pub mod tracer;
+pub mod access;
+pub mod targets;
+pub mod cli;
+pub mod report;
+pub mod maps;
+pub mod elf;
+pub mod dwarf;
+pub mod symbols;
+pub mod leakage;
+pub mod normalize;
+pub mod latency;
+pub mod taint;
+pub mod timing;
+pub mod inputs;
+pub mod tracefile;
+pub mod golden;
+pub mod breakpoint;
+
+/// Which properties of the traces to compare between runs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Checks {
+    Instructions,
+    Memory,
+    Both
+}
+
+impl Checks {
+    pub fn instructions(&self) -> bool {
+        *self != Checks::Memory
+    }
+
+    pub fn memory(&self) -> bool {
+        *self != Checks::Instructions
+    }
+}
+
+pub struct Config {
+    /// Number of traced runs of the target for each input class.
+    pub iterations: usize,
+    /// Seed for the RNG that `generate_inputs` is handed.
+    pub seed: u64,
+    /// Restrict the input classes to these. None runs every class the target
+    /// opts into.
+    pub input_classes: Option<Vec<InputClass>>,
+    pub checks: Checks,
+    /// How memory accesses are compared.
+    pub model: LeakageModel,
+    /// How addresses are made independent of the memory layout of each run.
+    pub normalization: Normalization,
+    /// If set, executing any of these instructions inside `run` is a failure.
+    pub latency: Option<LatencyPolicy>,
+    /// Track the flow of the target's secret inputs through each run.
+    pub taint: bool,
+    /// Instead of tracing, time the target natively and run a fixed-vs-random
+    /// Welch's t-test on the measurements.
+    pub timing: bool,
+    /// Number of measurements taken in timing mode.
+    pub measurements: usize,
+    /// |t| above which timing mode reports a leak.
+    pub t_threshold: f64,
+    /// Write every traced run to a file in this directory.
+    pub save_dir: Option<PathBuf>,
+    /// Compare runs against this saved trace instead of the first run.
+    pub reference: Option<PathBuf>,
+    /// Check every run against the trace shape recorded for the target in this
+    /// directory, recording it first if there isn't one.
+    pub golden_dir: Option<PathBuf>,
+    /// Re-record the shapes in `golden_dir` instead of checking them.
+    pub update_golden: bool,
+    /// Trace calls to this function instead of each target's `traced_function`.
+    pub function: Option<String>
+}
+
+impl Default for Config {
+    /// The settings the command line starts from: every check, exact addresses
+    /// and a random seed.
+    fn default() -> Config {
+        Config {
+            iterations: 64,
+            seed: rand::random(),
+            input_classes: None,
+            checks: Checks::Both,
+            model: LeakageModel::Exact,
+            normalization: Normalization::Absolute,
+            latency: None,
+            taint: false,
+            timing: false,
+            measurements: 1000000,
+            t_threshold: 4.5,
+            save_dir: None,
+            reference: None,
+            golden_dir: None,
+            update_golden: false,
+            function: None
+        }
+    }
+}
+
+/// Something whose instruction and memory access trace should not depend on its
+/// inputs. The tracer forks, calls `setup` once in the child, and then for every
+/// iteration generates fresh inputs and single-steps through `run`.
+pub trait ConstantTimeTarget {
+    /// A short name used to identify the target in output.
+    fn name(&self) -> &str;
+
+    /// Called once, untraced, before the first run. Anything lazily initialized
+    /// (dynamic symbol resolution, static tables) should be touched here so it
+    /// doesn't show up as a difference in the first traced run.
+    fn setup(&mut self) { }
+
+    /// Produce the inputs for the next run.
+    fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>>;
+
+    /// The input classes the target should be run with. The byte-pattern classes
+    /// reuse the shape of `generate_inputs`' output, so targets whose inputs must
+    /// satisfy some relationship should only opt into those that preserve it.
+    fn input_classes(&self) -> Vec<InputClass> { vec![InputClass::Random] }
+
+    /// Indexes of the inputs that are secret, for taint tracking.
+    fn secret_inputs(&self) -> Vec<usize> { Vec::new() }
+
+    /// If set, only calls to this function (by its mangled or demangled name) are
+    /// traced, using breakpoints rather than the signals normally raised around
+    /// `run`. `run` must call it exactly once.
+    fn traced_function(&self) -> Option<&str> { None }
+
+    /// The code under test. Only what happens inside this call (or the call to
+    /// `traced_function` it makes) is traced.
+    fn run(&mut self, inputs: &[Vec<u8>]);
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// One line of /proc/<pid>/maps.
+#[derive(Clone, Debug)]
+pub struct Mapping {
+    pub start: u64,
+    pub end: u64,
+    pub perms: String,
+    pub offset: u64,
+    /// The backing file, or a pseudo path such as "[heap]" or "[stack]". None for
+    /// anonymous mappings.
+    pub path: Option<String>
+}
+
+impl Mapping {
+    pub fn contains(&self, addr: u64) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.perms.as_bytes().get(2) == Some(&b'x')
+    }
+
+    /// True if the mapping is backed by a file rather than being anonymous or one
+    /// of the kernel's pseudo mappings.
+    pub fn is_file(&self) -> bool {
+        match self.path {
+            Some(ref path) => path.starts_with('/'),
+            None => false
+        }
+    }
+}
+
+fn parse_line(line: &str) -> Option<Mapping> {
+    let mut fields = line.splitn(5, ' ');
+    let range = fields.next()?;
+    let perms = fields.next()?;
+    let offset = fields.next()?;
+    let _dev = fields.next();
+    // The inode field is followed by padding before the path.
+    let path = match fields.next() {
+        Some(rest) => {
+            let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+            if rest.is_empty() { None } else { Some(rest.to_string()) }
+        }
+        None => None
+    };
+
+    let mut bounds = range.split('-');
+    let start = u64::from_str_radix(bounds.next()?, 16).ok()?;
+    let end = u64::from_str_radix(bounds.next()?, 16).ok()?;
+    let offset = u64::from_str_radix(offset, 16).ok()?;
+
+    Some(Mapping {
+        start,
+        end,
+        perms: perms.to_string(),
+        offset,
+        path
+    })
+}
+
+/// Read the memory map of `pid`.
+pub fn read_maps(pid: libc::pid_t) -> Vec<Mapping> {
+    let path = PathBuf::from(format!("/proc/{}/maps", pid));
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => panic!("Couldn't open {}: {}", path.display(), e)
+    };
+    let reader = BufReader::new(file);
+    let mut maps = Vec::new();
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => panic!("Couldn't read {}: {}", path.display(), e)
+        };
+        match parse_line(line.trim_end()) {
+            Some(mapping) => maps.push(mapping),
+            None => println!("Ignoring unparseable map entry: {}", line)
+        }
+    }
+    maps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_file_backed_line() {
+        let line = "7f3a1c000000-7f3a1c022000 r-xp 00028000 fd:01 1316532                    /usr/lib/x86_64-linux-gnu/libc.so.6";
+        let mapping = parse_line(line).unwrap();
+        assert_eq!(mapping.start, 0x7f3a1c000000);
+        assert_eq!(mapping.end, 0x7f3a1c022000);
+        assert_eq!(mapping.perms, "r-xp");
+        assert_eq!(mapping.offset, 0x28000);
+        assert_eq!(mapping.path.as_deref(), Some("/usr/lib/x86_64-linux-gnu/libc.so.6"));
+        assert!(mapping.is_executable());
+        assert!(mapping.is_file());
+    }
+
+    #[test]
+    fn parse_anonymous_line() {
+        let mapping = parse_line("7f3a1c200000-7f3a1c20d000 rw-p 00000000 00:00 0").unwrap();
+        assert_eq!(mapping.start, 0x7f3a1c200000);
+        assert_eq!(mapping.end, 0x7f3a1c20d000);
+        assert_eq!(mapping.perms, "rw-p");
+        assert_eq!(mapping.offset, 0);
+        assert_eq!(mapping.path, None);
+        assert!(!mapping.is_executable());
+        assert!(!mapping.is_file());
+    }
+}
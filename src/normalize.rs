@@ -0,0 +1,185 @@
+use std::fmt;
+
+use crate::maps::Mapping;
+
+/// How addresses are made comparable between runs whose memory layout differs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Normalization {
+    /// Compare absolute addresses.
+    Absolute,
+    /// Compare offsets into the mapping (stack, heap, an object's segments) that an
+    /// address falls in.
+    Mapping,
+    /// As `Mapping`, but addresses inside one of the target's input buffers, or
+    /// the slice of them `run` is handed, are compared as offsets into that
+    /// allocation, so inputs that were allocated at different places don't count
+    /// as a difference.
+    Allocation
+}
+
+impl Normalization {
+    pub fn parse(s: &str) -> Option<Normalization> {
+        match s {
+            "none" => Some(Normalization::Absolute),
+            "mapping" => Some(Normalization::Mapping),
+            "allocation" => Some(Normalization::Allocation),
+            _ => None
+        }
+    }
+}
+
+/// What a normalized address is relative to.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Region {
+    Absolute,
+    /// A mapping, named after its backing file or pseudo path ([heap], [stack]).
+    /// Anonymous mappings are named after the closest named mapping below them,
+    /// since that is usually the object whose .bss they hold.
+    Mapping(String),
+    /// One of the target's inputs, by index.
+    Input(usize),
+    /// The slice of input buffers itself.
+    InputList
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Region::Absolute => write!(f, "absolute"),
+            Region::Mapping(ref name) => write!(f, "{}", name),
+            Region::Input(i) => write!(f, "input {}", i),
+            Region::InputList => write!(f, "inputs")
+        }
+    }
+}
+
+struct Area {
+    start: u64,
+    end: u64,
+    name: String,
+    // The lowest address of any mapping with the same name, which offsets are
+    // taken from so that all of an object's segments share a base.
+    base: u64
+}
+
+/// The layout of a tracee's memory at the end of one run.
+pub struct AddressSpace {
+    areas: Vec<Area>,
+    // (address, length) of each input buffer.
+    inputs: Vec<(u64, u64)>,
+    // (address, length) of the slice of them.
+    input_list: (u64, u64)
+}
+
+impl AddressSpace {
+    pub fn new(maps: &[Mapping], inputs: &[(u64, u64)], input_list: (u64, u64)) -> AddressSpace {
+        let mut areas: Vec<Area> = Vec::with_capacity(maps.len());
+        let mut last_name = "[anon]".to_string();
+        for mapping in maps.iter() {
+            let name = match mapping.path {
+                Some(ref path) => {
+                    last_name = path.clone();
+                    path.clone()
+                }
+                None => format!("{} (anon)", last_name)
+            };
+            let base = areas.iter()
+                .filter(|a| a.name == name)
+                .map(|a| a.base)
+                .next()
+                .unwrap_or(mapping.start);
+            areas.push(Area {
+                start: mapping.start,
+                end: mapping.end,
+                name,
+                base
+            });
+        }
+        AddressSpace {
+            areas,
+            inputs: inputs.to_vec(),
+            input_list
+        }
+    }
+
+    /// Split `addr` into the region it belongs to and its offset within it.
+    pub fn normalize(&self, addr: u64, how: Normalization) -> (Region, u64) {
+        if how == Normalization::Absolute {
+            return (Region::Absolute, addr);
+        }
+        if how == Normalization::Allocation {
+            for (i, &(start, len)) in self.inputs.iter().enumerate() {
+                if start <= addr && addr - start < len {
+                    return (Region::Input(i), addr - start);
+                }
+            }
+            let (start, len) = self.input_list;
+            if start <= addr && addr - start < len {
+                return (Region::InputList, addr - start);
+            }
+        }
+        for area in self.areas.iter() {
+            if area.start <= addr && addr < area.end {
+                return (Region::Mapping(area.name.clone()), addr - area.base);
+            }
+        }
+        (Region::Absolute, addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(start: u64, end: u64, path: Option<&str>) -> Mapping {
+        Mapping { start, end, perms: "rw-p".to_string(), offset: 0, path: path.map(|p| p.to_string()) }
+    }
+
+    fn address_space() -> AddressSpace {
+        let maps = [
+            mapping(0x400000, 0x401000, Some("/bin/target")),
+            mapping(0x401000, 0x403000, Some("/bin/target")),
+            mapping(0x403000, 0x404000, None),
+            mapping(0x1000000, 0x1021000, Some("[heap]"))
+        ];
+        AddressSpace::new(&maps, &[(0x1000100, 16), (0x1000200, 4)], (0x1000010, 48))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Normalization::parse("none"), Some(Normalization::Absolute));
+        assert_eq!(Normalization::parse("mapping"), Some(Normalization::Mapping));
+        assert_eq!(Normalization::parse("allocation"), Some(Normalization::Allocation));
+        assert_eq!(Normalization::parse("absolute"), None);
+        assert_eq!(Normalization::parse(""), None);
+    }
+
+    #[test]
+    fn absolute() {
+        let space = address_space();
+        assert_eq!(space.normalize(0x1000104, Normalization::Absolute), (Region::Absolute, 0x1000104));
+    }
+
+    #[test]
+    fn relative_to_mapping() {
+        let space = address_space();
+        let target = Region::Mapping("/bin/target".to_string());
+        assert_eq!(space.normalize(0x400010, Normalization::Mapping), (target.clone(), 0x10));
+        // Later segments of the same object share its base.
+        assert_eq!(space.normalize(0x402000, Normalization::Mapping), (target, 0x2000));
+        assert_eq!(space.normalize(0x403008, Normalization::Mapping),
+                   (Region::Mapping("/bin/target (anon)".to_string()), 8));
+        assert_eq!(space.normalize(0x1000104, Normalization::Mapping), (Region::Mapping("[heap]".to_string()), 0x104));
+        assert_eq!(space.normalize(0x5000, Normalization::Mapping), (Region::Absolute, 0x5000));
+    }
+
+    #[test]
+    fn relative_to_allocation() {
+        let space = address_space();
+        assert_eq!(space.normalize(0x1000104, Normalization::Allocation), (Region::Input(0), 4));
+        assert_eq!(space.normalize(0x1000203, Normalization::Allocation), (Region::Input(1), 3));
+        assert_eq!(space.normalize(0x1000018, Normalization::Allocation), (Region::InputList, 8));
+        // Just past the end of an input is ordinary heap.
+        assert_eq!(space.normalize(0x1000204, Normalization::Allocation), (Region::Mapping("[heap]".to_string()), 0x204));
+    }
+}
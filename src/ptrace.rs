@@ -0,0 +1,257 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::result;
+
+
+/// A failed system call and the errno it set.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Error {
+    pub call: &'static str,
+    pub errno: libc::c_int
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.call, io::Error::from_raw_os_error(self.errno))
+    }
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+fn errno() -> libc::c_int {
+    io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+fn last_error(call: &'static str) -> Error {
+    Error { call, errno: errno() }
+}
+
+fn request(call: &'static str, request: libc::c_uint, tid: libc::pid_t, addr: usize, data: usize) -> Result<()> {
+    if unsafe { libc::ptrace(request, tid, addr, data) } == -1 {
+        Err(last_error(call))
+    } else {
+        Ok(())
+    }
+}
+
+/// Why a tracee stopped, or stopped existing.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StopEvent {
+    /// A signal is about to be delivered to the thread. SIGTRAP means a single
+    /// step finished or a breakpoint was hit; anything else is meant for the
+    /// tracee.
+    Signal(libc::c_int),
+    /// The thread started a new thread, which will stop with SIGSTOP before it
+    /// runs.
+    Clone(libc::pid_t),
+    /// The thread exited with a status.
+    Exited(libc::c_int),
+    /// The thread was killed by a signal.
+    Killed(libc::c_int)
+}
+
+/// A thread traced with ptrace. Apart from `wait_event`, the methods may only be
+/// called while the thread is stopped.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Tracee {
+    pub tid: libc::pid_t
+}
+
+impl Tracee {
+    pub fn new(tid: libc::pid_t) -> Tracee {
+        Tracee { tid }
+    }
+
+    /// Ask to be traced by the parent process. Called in the child after forking.
+    pub fn trace_me() -> Result<()> {
+        request("PTRACE_TRACEME", libc::PTRACE_TRACEME, 0, 0, 0)
+    }
+
+    /// Attach to a running thread. It stops with SIGSTOP soon afterwards.
+    pub fn attach(tid: libc::pid_t) -> Result<Tracee> {
+        request("PTRACE_ATTACH", libc::PTRACE_ATTACH, tid, 0, 0)?;
+        Ok(Tracee::new(tid))
+    }
+
+    pub fn set_options(&self, options: libc::c_int) -> Result<()> {
+        request("PTRACE_SETOPTIONS", libc::PTRACE_SETOPTIONS, self.tid, 0, options as usize)
+    }
+
+    /// Let the thread run, delivering `sig` to it unless it's 0.
+    pub fn cont(&self, sig: libc::c_int) -> Result<()> {
+        request("PTRACE_CONT", libc::PTRACE_CONT, self.tid, 0, sig as usize)
+    }
+
+    /// Execute a single instruction, delivering `sig` first unless it's 0.
+    pub fn step(&self, sig: libc::c_int) -> Result<()> {
+        request("PTRACE_SINGLESTEP", libc::PTRACE_SINGLESTEP, self.tid, 0, sig as usize)
+    }
+
+    pub fn detach(&self) -> Result<()> {
+        request("PTRACE_DETACH", libc::PTRACE_DETACH, self.tid, 0, 0)
+    }
+
+    pub fn regs(&self) -> Result<libc::user_regs_struct> {
+        let mut regs = zeroed_regs();
+        request("PTRACE_GETREGS", libc::PTRACE_GETREGS, self.tid, 0,
+                &mut regs as *mut _ as usize)?;
+        Ok(regs)
+    }
+
+    pub fn set_regs(&self, regs: &libc::user_regs_struct) -> Result<()> {
+        request("PTRACE_SETREGS", libc::PTRACE_SETREGS, self.tid, 0, regs as *const _ as usize)
+    }
+
+    /// Read the word at `addr`.
+    pub fn peek(&self, addr: u64) -> Result<u64> {
+        // The word read is the return value, so the only way to tell a failure
+        // apart from a word that happens to be -1 is to clear errno first.
+        unsafe { *libc::__errno_location() = 0 };
+        let word = unsafe { libc::ptrace(libc::PTRACE_PEEKTEXT, self.tid, addr as usize, 0) };
+        if word == -1 && errno() != 0 {
+            Err(last_error("PTRACE_PEEKTEXT"))
+        } else {
+            Ok(word as u64)
+        }
+    }
+
+    /// Write the word at `addr`. Code can be written even though it isn't
+    /// writable by the tracee.
+    pub fn poke(&self, addr: u64, word: u64) -> Result<()> {
+        request("PTRACE_POKETEXT", libc::PTRACE_POKETEXT, self.tid, addr as usize, word as usize)
+    }
+
+    /// Read as much of `buf` as can be read from `addr`, which may stop short at
+    /// the end of a mapping. Returns the number of bytes read, or an error if
+    /// nothing could be.
+    pub fn read_mem(&self, addr: u64, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let word = match self.peek(addr + read as u64) {
+                Ok(word) => word,
+                Err(e) => if read == 0 { return Err(e) } else { break }
+            };
+            let bytes = word.to_le_bytes();
+            let len = bytes.len().min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&bytes[..len]);
+            read += len;
+        }
+        Ok(read)
+    }
+
+    /// Send `sig` to this thread alone.
+    pub fn signal(&self, sig: libc::c_int) -> Result<()> {
+        let result = unsafe {
+            libc::syscall(libc::SYS_tkill, self.tid as libc::c_long, sig as libc::c_long)
+        };
+        if result == -1 {
+            Err(last_error("tkill"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Kill the whole process the thread belongs to.
+    pub fn kill(&self) -> Result<()> {
+        if unsafe { libc::kill(self.tid, libc::SIGKILL) } == -1 {
+            Err(last_error("kill"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wait for the thread to stop or exit.
+    pub fn wait_event(&self) -> Result<StopEvent> {
+        let mut status: libc::c_int = 0;
+        if unsafe { libc::waitpid(self.tid, &mut status, libc::__WALL) } == -1 {
+            return Err(last_error("waitpid"));
+        }
+        self.decode(status)
+    }
+
+    fn decode(&self, status: libc::c_int) -> Result<StopEvent> {
+        if libc::WIFEXITED(status) {
+            return Ok(StopEvent::Exited(libc::WEXITSTATUS(status)));
+        }
+        if libc::WIFSIGNALED(status) {
+            return Ok(StopEvent::Killed(libc::WTERMSIG(status)));
+        }
+        let sig = libc::WSTOPSIG(status);
+        // The PTRACE_EVENT_* that caused a SIGTRAP stop is above the stop signal.
+        if sig == libc::SIGTRAP && status >> 16 == libc::PTRACE_EVENT_CLONE {
+            let mut new_tid: libc::c_ulong = 0;
+            request("PTRACE_GETEVENTMSG", libc::PTRACE_GETEVENTMSG, self.tid, 0,
+                    &mut new_tid as *mut _ as usize)?;
+            return Ok(StopEvent::Clone(new_tid as libc::pid_t));
+        }
+        Ok(StopEvent::Signal(sig))
+    }
+}
+
+/// Wait for any tracee to stop or exit.
+pub fn wait_any() -> Result<(Tracee, StopEvent)> {
+    let mut status: libc::c_int = 0;
+    let tid = unsafe { libc::waitpid(-1, &mut status, libc::__WALL) };
+    if tid == -1 {
+        return Err(last_error("waitpid"));
+    }
+    let tracee = Tracee::new(tid);
+    let event = tracee.decode(status)?;
+    Ok((tracee, event))
+}
+
+/// A register set with every register zero.
+pub fn zeroed_regs() -> libc::user_regs_struct {
+    // It's plain integers, for which zero is a valid value.
+    unsafe { mem::zeroed() }
+}
+
+/// Which side of a `fork` the caller is on.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fork {
+    Parent(libc::pid_t),
+    Child
+}
+
+/// Fork the calling process. The child only has a copy of the calling thread.
+pub fn fork() -> Result<Fork> {
+    match unsafe { libc::fork() } {
+        -1 => Err(last_error("fork")),
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid))
+    }
+}
+
+/// Send `sig` to the calling thread.
+pub fn raise(sig: libc::c_int) -> Result<()> {
+    if unsafe { libc::raise(sig) } != 0 {
+        Err(last_error("raise"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Start `program` with `args` (which include its name) and the `NAME=value`
+/// strings in `env`, traced by this process. It stops with SIGTRAP once the
+/// exec has happened, or exits with status 127 if it couldn't.
+pub fn spawn(program: &CStr, args: &[CString], env: &[CString]) -> Result<Tracee> {
+    // Nothing can be allocated between fork and exec, since another thread may
+    // have held the allocator's lock when this one forked.
+    let mut argv: Vec<*const libc::c_char> = args.iter().map(|a| a.as_ptr()).collect();
+    argv.push(ptr::null());
+    let mut envp: Vec<*const libc::c_char> = env.iter().map(|e| e.as_ptr()).collect();
+    envp.push(ptr::null());
+
+    match fork()? {
+        Fork::Parent(pid) => Ok(Tracee::new(pid)),
+        Fork::Child => unsafe {
+            if Tracee::trace_me().is_ok() {
+                libc::execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            }
+            libc::_exit(127)
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use std::cmp;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use crate::access::MemAccess;
+use crate::symbols::Symbolizer;
+use crate::decoder::disassemble;
+use crate::tracer::Trace;
+use crate::leakage::LeakageModel;
+use crate::normalize::{Normalization, Region};
+use crate::Config;
+
+/// Number of steps shown on either side of a divergence.
+const CONTEXT: usize = 8;
+
+/// Where two traces first stop agreeing.
+pub struct Divergence {
+    /// Index of the first differing step in the first trace.
+    pub step_a: usize,
+    /// Index of the first differing step in the second trace.
+    pub step_b: usize,
+    /// A description of the check that failed.
+    pub what: String,
+    /// The model memory accesses were compared under, which they are also shown
+    /// in terms of.
+    pub model: LeakageModel
+}
+
+fn compare_instructions(a: &Trace, b: &Trace) -> Option<usize> {
+    let len = cmp::min(a.steps.len(), b.steps.len());
+    for i in 0..len {
+        if a.steps[i].rip != b.steps[i].rip {
+            return Some(i);
+        }
+    }
+    if a.steps.len() != b.steps.len() {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+// Flatten the memory accesses of a trace into (step, region, access) triples, with
+// each access's address made relative to its region.
+fn mem_accesses(trace: &Trace, normalization: Normalization) -> Vec<(usize, Region, MemAccess)> {
+    let address_space = trace.address_space();
+    let mut accesses = Vec::new();
+    for (i, step) in trace.steps.iter().enumerate() {
+        for &access in &step.mem_accesses {
+            let (region, offset) = address_space.normalize(access.addr, normalization);
+            accesses.push((i, region, MemAccess { addr: offset, ..access }));
+        }
+    }
+    accesses
+}
+
+fn compare_mem_accesses(a: &Trace, b: &Trace, config: &Config) -> Option<(usize, usize)> {
+    let accesses_a = mem_accesses(a, config.normalization);
+    let accesses_b = mem_accesses(b, config.normalization);
+    let len = cmp::min(accesses_a.len(), accesses_b.len());
+    for i in 0..len {
+        let (step_a, ref region_a, access_a) = accesses_a[i];
+        let (step_b, ref region_b, access_b) = accesses_b[i];
+        if region_a != region_b || !config.model.same(&access_a, &access_b) {
+            return Some((step_a, step_b));
+        }
+    }
+    if accesses_a.len() != accesses_b.len() {
+        let step_after = |accesses: &[(usize, Region, MemAccess)], trace: &Trace| {
+            if len < accesses.len() { accesses[len].0 } else { trace.steps.len() }
+        };
+        Some((step_after(&accesses_a, a), step_after(&accesses_b, b)))
+    } else {
+        None
+    }
+}
+
+/// Compare two traces, returning the first point where they differ in any of the
+/// properties selected by `config.checks`, with memory accesses compared under
+/// `config.model`.
+pub fn compare(a: &Trace, b: &Trace, config: &Config) -> Option<Divergence> {
+    if config.checks.instructions() {
+        if let Some(step) = compare_instructions(a, b) {
+            return Some(Divergence {
+                step_a: step,
+                step_b: step,
+                what: "instructions".to_string(),
+                model: config.model
+            });
+        }
+    }
+    if config.checks.memory() {
+        if let Some((step_a, step_b)) = compare_mem_accesses(a, b, config) {
+            let what = match config.model {
+                LeakageModel::Exact => "memory accesses".to_string(),
+                model => format!("memory accesses under the {:?} leakage model", model)
+            };
+            return Some(Divergence { step_a, step_b, what, model: config.model });
+        }
+    }
+    None
+}
+
+/// A hash of everything `compare` looks at, so runs can be grouped into classes
+/// that behaved identically.
+pub fn trace_hash(trace: &Trace, config: &Config) -> u64 {
+    let mut state = DefaultHasher::new();
+    if config.checks.instructions() {
+        for step in trace.steps.iter() {
+            step.rip.hash(&mut state);
+        }
+    }
+    if config.checks.memory() {
+        for (_, region, access) in mem_accesses(trace, config.normalization) {
+            region.hash(&mut state);
+            match config.model {
+                LeakageModel::Exact => access.hash(&mut state),
+                model => model.observe(&access).hash(&mut state)
+            }
+        }
+    }
+    state.finish()
+}
+
+/// A group of runs whose traces hashed the same.
+pub struct Cluster {
+    pub hash: u64,
+    pub runs: Vec<usize>
+}
+
+/// Summarize how many runs differed from the reference run and how the runs group
+/// into equivalence classes. The reference's class is listed first.
+pub fn print_summary(total_runs: usize, clusters: &[Cluster]) {
+    let matching = clusters.first().map(|c| c.runs.len()).unwrap_or(0);
+    println!("{} of {} runs diverged from the reference, {} distinct traces:",
+             total_runs - matching, total_runs, clusters.len());
+    for (i, cluster) in clusters.iter().enumerate() {
+        let mut runs: Vec<String> = cluster.runs.iter().take(16).map(|r| r.to_string()).collect();
+        if cluster.runs.len() > 16 {
+            runs.push("...".to_string());
+        }
+        println!("    {:016x}{} {:>6} runs: {}", cluster.hash, if i == 0 { " (reference)" } else { "            " },
+                 cluster.runs.len(), runs.join(", "));
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+fn describe_step(trace: &Trace, step: usize, symbols: &Symbolizer) -> String {
+    if step < trace.steps.len() {
+        let rip = trace.steps[step].rip;
+        format!("rip {:x} in {}", rip, symbols.describe(rip))
+    } else {
+        "end of run".to_string()
+    }
+}
+
+// `label` with its first letter in upper case, to start a line with.
+fn capitalized(label: &str) -> String {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+fn print_context(trace: &Trace, label: &str, around: usize, model: LeakageModel, symbols: &Symbolizer) {
+    println!("{} around step {}:", capitalized(label), around);
+    let start = around.saturating_sub(CONTEXT);
+    let end = cmp::min(around + CONTEXT + 1, trace.steps.len());
+    let mut last_location = String::new();
+    for i in start..end {
+        let step = &trace.steps[i];
+        // Only print the source location when it changes, as a heading for the
+        // instructions that follow.
+        let location = symbols.lookup(step.rip).map_or_else(|| "??".to_string(), |l| l.source());
+        if location != last_location {
+            println!("   {}", location);
+            last_location = location;
+        }
+        let marker = if i == around { "=>" } else { "  " };
+        let mut line = format!("{} {:>8} {:016x}  {}", marker, i, step.rip, disassemble(step.rip, step.code()));
+        if !step.mem_accesses.is_empty() {
+            let addrs: Vec<String> = step.mem_accesses.iter().map(|a| {
+                let mut text = format!("{}", a);
+                if model != LeakageModel::Exact {
+                    text.push_str(&format!(" [{}]", model.describe(a)));
+                }
+                if let Some(location) = symbols.lookup(a.addr) {
+                    text.push_str(&format!(" ({})", location));
+                }
+                text
+            }).collect();
+            line.push_str(&format!("    ; mem {}", addrs.join(", ")));
+        }
+        println!("{}", line);
+    }
+    if around >= trace.steps.len() {
+        println!("=> {:>8} (end of run)", around);
+    }
+}
+
+fn print_inputs(trace: &Trace, label: &str) {
+    println!("{} inputs ({}):", capitalized(label), trace.class.name());
+    for (i, input) in trace.inputs.iter().enumerate() {
+        println!("    [{}] {}", i, hex(input));
+    }
+}
+
+/// Print where traces `a` and `b` diverged, what was executing on each side, and
+/// the inputs that produced them. The labels say where each trace came from, such
+/// as "run 3" or the file it was read from.
+pub fn print_divergence(a: &Trace, label_a: &str, b: &Trace, label_b: &str, divergence: &Divergence,
+                        symbols: &Symbolizer) {
+    println!("{} and {} differ in {}", capitalized(label_a), label_b, divergence.what);
+    println!("    {}: step {}, {}", label_a, divergence.step_a, describe_step(a, divergence.step_a, symbols));
+    println!("    {}: step {}, {}", label_b, divergence.step_b, describe_step(b, divergence.step_b, symbols));
+    println!();
+    print_inputs(a, label_a);
+    print_inputs(b, label_b);
+    println!();
+    print_context(a, label_a, divergence.step_a, divergence.model, symbols);
+    println!();
+    print_context(b, label_b, divergence.step_b, divergence.model, symbols);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::AccessKind;
+    use crate::inputs::InputClass;
+    use crate::maps::Mapping;
+    use crate::tracer::Step;
+    use crate::Checks;
+
+    fn load(addr: u64) -> MemAccess {
+        MemAccess { addr, size: 8, kind: AccessKind::Load }
+    }
+
+    // A trace of the given (rip, accesses) steps, with a heap mapped at `heap`.
+    fn trace(heap: u64, steps: &[(u64, &[MemAccess])]) -> Trace {
+        Trace {
+            class: InputClass::Random,
+            inputs: Vec::new(),
+            input_addrs: Vec::new(),
+            inputs_addr: 0,
+            steps: steps.iter().map(|&(rip, accesses)| Step {
+                rip,
+                code: [0x90; 15],
+                code_len: 1,
+                mem_accesses: accesses.to_vec()
+            }).collect(),
+            maps: vec![Mapping { start: heap, end: heap + 0x21000, perms: "rw-p".to_string(), offset: 0,
+                                 path: Some("[heap]".to_string()) }]
+        }
+    }
+
+    fn config(checks: Checks, model: LeakageModel, normalization: Normalization) -> Config {
+        Config { checks, model, normalization, ..Config::default() }
+    }
+
+    #[test]
+    fn identical() {
+        let a = trace(0x1000000, &[(0x1000, &[load(0x1000040)]), (0x1004, &[])]);
+        let b = trace(0x1000000, &[(0x1000, &[load(0x1000040)]), (0x1004, &[])]);
+        let config = config(Checks::Both, LeakageModel::Exact, Normalization::Absolute);
+        assert!(compare(&a, &b, &config).is_none());
+        assert_eq!(trace_hash(&a, &config), trace_hash(&b, &config));
+    }
+
+    #[test]
+    fn instruction_divergence() {
+        let a = trace(0x1000000, &[(0x1000, &[]), (0x1004, &[]), (0x1008, &[])]);
+        let b = trace(0x1000000, &[(0x1000, &[]), (0x1006, &[]), (0x1008, &[])]);
+        let config = config(Checks::Both, LeakageModel::Exact, Normalization::Absolute);
+        let divergence = compare(&a, &b, &config).unwrap();
+        assert_eq!((divergence.step_a, divergence.step_b), (1, 1));
+        assert_eq!(divergence.what, "instructions");
+        assert_ne!(trace_hash(&a, &config), trace_hash(&b, &config));
+
+        // A trace that stops early diverges where it stops.
+        let c = trace(0x1000000, &[(0x1000, &[]), (0x1004, &[])]);
+        let divergence = compare(&a, &c, &config).unwrap();
+        assert_eq!((divergence.step_a, divergence.step_b), (2, 2));
+
+        // Unless only memory is checked.
+        let config = self::config(Checks::Memory, LeakageModel::Exact, Normalization::Absolute);
+        assert!(compare(&a, &b, &config).is_none());
+        assert_eq!(trace_hash(&a, &config), trace_hash(&b, &config));
+    }
+
+    #[test]
+    fn memory_divergence() {
+        // The second trace makes one extra step before the differing access, so
+        // the steps are reported separately for each trace.
+        let a = trace(0x1000000, &[(0x1000, &[load(0x1000040)]), (0x1004, &[load(0x1000080)])]);
+        let b = trace(0x1000000, &[(0x1000, &[load(0x1000040)]), (0x1002, &[]),
+                                   (0x1004, &[load(0x1000048)])]);
+        let config = config(Checks::Memory, LeakageModel::Exact, Normalization::Absolute);
+        let divergence = compare(&a, &b, &config).unwrap();
+        assert_eq!((divergence.step_a, divergence.step_b), (1, 2));
+        assert_eq!(divergence.what, "memory accesses");
+        assert_ne!(trace_hash(&a, &config), trace_hash(&b, &config));
+
+        // A missing access diverges at the step after the last access.
+        let c = trace(0x1000000, &[(0x1000, &[load(0x1000040)]), (0x1004, &[])]);
+        let divergence = compare(&a, &c, &config).unwrap();
+        assert_eq!((divergence.step_a, divergence.step_b), (1, 2));
+    }
+
+    #[test]
+    fn leakage_model() {
+        // 0x1000040 and 0x1000078 are in the same cache line, 0x1000080 isn't.
+        let a = trace(0x1000000, &[(0x1000, &[load(0x1000040)])]);
+        let b = trace(0x1000000, &[(0x1000, &[load(0x1000078)])]);
+        let c = trace(0x1000000, &[(0x1000, &[load(0x1000080)])]);
+        let config = config(Checks::Memory, LeakageModel::CacheLine, Normalization::Absolute);
+        assert!(compare(&a, &b, &config).is_none());
+        assert_eq!(trace_hash(&a, &config), trace_hash(&b, &config));
+        let divergence = compare(&a, &c, &config).unwrap();
+        assert_eq!(divergence.what, "memory accesses under the CacheLine leakage model");
+        assert_eq!(divergence.model, LeakageModel::CacheLine);
+        assert_ne!(trace_hash(&a, &config), trace_hash(&c, &config));
+    }
+
+    #[test]
+    fn normalization() {
+        // The same offset into heaps mapped at different addresses.
+        let a = trace(0x1000000, &[(0x1000, &[load(0x1000040)])]);
+        let b = trace(0x2000000, &[(0x1000, &[load(0x2000040)])]);
+        let config = config(Checks::Memory, LeakageModel::Exact, Normalization::Absolute);
+        assert!(compare(&a, &b, &config).is_some());
+        assert_ne!(trace_hash(&a, &config), trace_hash(&b, &config));
+        let config = self::config(Checks::Memory, LeakageModel::Exact, Normalization::Mapping);
+        assert!(compare(&a, &b, &config).is_none());
+        assert_eq!(trace_hash(&a, &config), trace_hash(&b, &config));
+    }
+
+    #[test]
+    fn grouping() {
+        // Runs group by hash the same way they compare: 0 and 2 behave the same,
+        // as do 1 and 3.
+        let runs = [
+            trace(0x1000000, &[(0x1000, &[load(0x1000040)])]),
+            trace(0x1000000, &[(0x1000, &[load(0x1000100)])]),
+            trace(0x1000000, &[(0x1000, &[load(0x1000040)])]),
+            trace(0x1000000, &[(0x1000, &[load(0x1000100)])]),
+            trace(0x1000000, &[(0x1000, &[]), (0x1004, &[])])
+        ];
+        let config = config(Checks::Both, LeakageModel::Exact, Normalization::Absolute);
+        let mut clusters: Vec<Cluster> = Vec::new();
+        for (run, trace) in runs.iter().enumerate() {
+            let hash = trace_hash(trace, &config);
+            match clusters.iter_mut().find(|c| c.hash == hash) {
+                Some(cluster) => cluster.runs.push(run),
+                None => clusters.push(Cluster { hash, runs: vec![run] })
+            }
+            assert_eq!(compare(&runs[0], trace, &config).is_none(), hash == clusters[0].hash);
+        }
+        let groups: Vec<&[usize]> = clusters.iter().map(|c| c.runs.as_slice()).collect();
+        assert_eq!(groups, [&[0, 2][..], &[1, 3], &[4]]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::dwarf::LineTable;
+use crate::elf::{Elf, Symbol, PT_LOAD};
+use crate::maps;
+
+/// What an address in the tracee corresponds to.
+pub struct Location {
+    pub symbol: Option<String>,
+    /// Offset of the address from the start of `symbol`.
+    pub offset: u64,
+    pub file: Option<String>,
+    pub line: Option<u64>,
+    /// The object the address falls in, if it isn't covered by a symbol.
+    pub object: Option<String>
+}
+
+impl Location {
+    /// The symbol (or object) and source line, without the offset, so that it is
+    /// the same for every instruction of a line.
+    pub fn source(&self) -> String {
+        let name = self.symbol.as_ref().or(self.object.as_ref()).map_or("??", |name| name.as_str());
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => format!("{} at {}:{}", name, file, line),
+            _ => name.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.symbol {
+            Some(ref symbol) if self.offset == 0 => write!(f, "{}", symbol)?,
+            Some(ref symbol) => write!(f, "{}+{:#x}", symbol, self.offset)?,
+            None => match self.object {
+                Some(ref object) => write!(f, "{}+{:#x}", object, self.offset)?,
+                None => write!(f, "??")?
+            }
+        }
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, " at {}:{}", file, line),
+            _ => Ok(())
+        }
+    }
+}
+
+// An object file as loaded into the tracee.
+struct Object {
+    path: String,
+    // Difference between the addresses in the file and where it is mapped.
+    bias: u64,
+    start: u64,
+    end: u64,
+    symbols: Vec<Symbol>,
+    lines: LineTable
+}
+
+impl Object {
+    fn lookup(&self, addr: u64) -> Location {
+        let file_addr = addr - self.bias;
+        let mut location = Location {
+            symbol: None,
+            offset: file_addr,
+            file: None,
+            line: None,
+            object: Some(self.path.clone())
+        };
+
+        // Symbols are sorted by address; find the last one starting at or before
+        // the address and check it actually covers it.
+        let (mut lo, mut hi) = (0, self.symbols.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.symbols[mid].value <= file_addr {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo > 0 {
+            let symbol = &self.symbols[lo - 1];
+            if file_addr < symbol.value + symbol.size || (symbol.size == 0 && file_addr == symbol.value) {
+                location.symbol = Some(demangle(&symbol.name));
+                location.offset = file_addr - symbol.value;
+            }
+        }
+
+        if let Some((file, line)) = self.lines.lookup(file_addr) {
+            location.file = Some(file.to_string());
+            location.line = Some(line);
+        }
+
+        location
+    }
+}
+
+// Work out the load bias of an object from one of its mappings by finding the
+// PT_LOAD segment the mapped file offset belongs to.
+fn load_bias(elf: &Elf, mapping: &maps::Mapping) -> Option<u64> {
+    for ph in &elf.program_headers {
+        if ph.typ == PT_LOAD && ph.offset <= mapping.offset && mapping.offset < ph.offset + ph.filesz {
+            return Some(mapping.start - (ph.vaddr + (mapping.offset - ph.offset)));
+        }
+    }
+    None
+}
+
+/// Maps addresses in a tracee to symbols and source lines, using the ELF symbol
+/// tables and DWARF line information of its executable and every library it has
+/// mapped.
+pub struct Symbolizer {
+    objects: Vec<Object>
+}
+
+impl Symbolizer {
+    /// Load symbols for everything currently mapped into `pid`. This has to be done
+    /// while the tracee is still alive.
+    pub fn new(pid: libc::pid_t) -> Symbolizer {
+        let exe_link = format!("/proc/{}/exe", pid);
+        let exe = fs::read_link(&exe_link).ok().and_then(|path| path.to_str().map(|s| s.to_string()));
+
+        // Prefer /proc/<pid>/exe for the main binary so that this still works if
+        // the file has since been replaced on disk.
+        Symbolizer::load(&maps::read_maps(pid), |path: &str| {
+            if exe.as_deref() == Some(path) {
+                Elf::open(Path::new(&exe_link))
+            } else {
+                Elf::open(Path::new(path))
+            }
+        })
+    }
+
+    /// Load symbols for a memory map saved from an earlier run, using whatever is
+    /// on disk at the mapped paths now. If `build_id` is given, the executable
+    /// (the first file mapping) is skipped unless its build id still matches, so a
+    /// rebuilt binary doesn't produce misleading symbols.
+    pub fn from_maps(mappings: &[maps::Mapping], build_id: Option<&[u8]>) -> Symbolizer {
+        let exe = mappings.iter().find(|m| m.is_file()).and_then(|m| m.path.clone());
+        Symbolizer::load(mappings, |path: &str| {
+            let elf = Elf::open(Path::new(path));
+            match (elf, build_id) {
+                (Some(elf), Some(build_id)) if exe.as_deref() == Some(path) => {
+                    if elf.build_id().as_deref() == Some(build_id) {
+                        Some(elf)
+                    } else {
+                        println!("{} has been rebuilt since the trace was recorded; not using its symbols", path);
+                        None
+                    }
+                }
+                (elf, _) => elf
+            }
+        })
+    }
+
+    fn load<F: Fn(&str) -> Option<Elf>>(mappings: &[maps::Mapping], open: F) -> Symbolizer {
+        let mut loaded: HashMap<String, usize> = HashMap::new();
+        let mut objects: Vec<Object> = Vec::new();
+
+        for mapping in mappings.iter().filter(|m| m.is_file()) {
+            let path = mapping.path.as_ref().unwrap();
+            if let Some(&index) = loaded.get(path) {
+                let object = &mut objects[index];
+                object.start = cmp::min(object.start, mapping.start);
+                object.end = cmp::max(object.end, mapping.end);
+                continue;
+            }
+
+            let elf = match open(path) {
+                Some(elf) => elf,
+                None => continue
+            };
+            let bias = match load_bias(&elf, mapping) {
+                Some(bias) => bias,
+                None => continue
+            };
+
+            let mut symbols = elf.symbols();
+            symbols.sort_by_key(|a| a.value);
+            let lines = LineTable::new(&elf);
+
+            loaded.insert(path.clone(), objects.len());
+            objects.push(Object {
+                path: path.clone(),
+                bias,
+                start: mapping.start,
+                end: mapping.end,
+                symbols,
+                lines
+            });
+        }
+
+        Symbolizer { objects }
+    }
+
+    /// Symbolize an address in the tracee. Returns None if it isn't in any mapped
+    /// object file (the heap or stack, for example).
+    pub fn lookup(&self, addr: u64) -> Option<Location> {
+        self.objects.iter()
+            .find(|o| o.start <= addr && addr < o.end)
+            .map(|o| o.lookup(addr))
+    }
+
+    /// Where the function or object called `name` (mangled, or demangled without
+    /// the hash) is in the tracee.
+    pub fn address_of(&self, name: &str) -> Option<u64> {
+        for object in &self.objects {
+            for symbol in &object.symbols {
+                if symbol.name == name || demangle(&symbol.name) == name {
+                    return Some(symbol.value + object.bias);
+                }
+            }
+        }
+        None
+    }
+
+    /// A short description of `addr`: the symbol and line if known, otherwise the
+    /// raw address.
+    pub fn describe(&self, addr: u64) -> String {
+        match self.lookup(addr) {
+            Some(location) => format!("{}", location),
+            None => format!("{:#x}", addr)
+        }
+    }
+}
+
+/// Demangle a legacy (`_ZN...E`) Rust symbol, dropping the trailing hash. Anything
+/// else is returned as is.
+pub fn demangle(name: &str) -> String {
+    let mut rest = match name.strip_prefix("_ZN").and_then(|rest| rest.strip_suffix('E')) {
+        Some(rest) => rest,
+        None => return name.to_string()
+    };
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let len: usize = match rest[..digits].parse() {
+            Ok(len) => len,
+            Err(_) => return name.to_string()
+        };
+        if digits + len > rest.len() || !rest.is_char_boundary(digits + len) {
+            return name.to_string();
+        }
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+
+    // The last component is a hash of the form h0123456789abcdef.
+    if let Some(&last) = parts.last() {
+        if last.len() == 17 && last.starts_with('h') && last[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            parts.pop();
+        }
+    }
+
+    let mut demangled = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            demangled.push_str("::");
+        }
+        let part = if part.starts_with("_$") { &part[1..] } else { part };
+        demangled.push_str(&unescape(part));
+    }
+    demangled
+}
+
+fn unescape(part: &str) -> String {
+    let replacements = [
+        ("$SP$", "@"), ("$BP$", "*"), ("$RF$", "&"), ("$LT$", "<"), ("$GT$", ">"),
+        ("$LP$", "("), ("$RP$", ")"), ("$C$", ","), ("$u7e$", "~"), ("$u20$", " "),
+        ("$u27$", "'"), ("$u5b$", "["), ("$u5d$", "]"), ("$u7b$", "{"), ("$u7d$", "}"),
+        ("$u3b$", ";"), ("$u2b$", "+"), ("$u22$", "\""), ("..", "::")
+    ];
+    let mut s = part.to_string();
+    for (from, to) in replacements {
+        s = s.replace(from, to);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(offset: u64) -> Location {
+        Location {
+            symbol: Some("aes::soft::fixslice::aes128_encrypt".to_string()),
+            offset,
+            file: Some("src/soft/fixslice64.rs".to_string()),
+            line: Some(120),
+            object: None
+        }
+    }
+
+    #[test]
+    fn source_ignores_the_offset() {
+        assert_eq!(location(0x10).source(), location(0x24).source());
+        assert_eq!(location(0x10).source(), "aes::soft::fixslice::aes128_encrypt at src/soft/fixslice64.rs:120");
+        assert_eq!(format!("{}", location(0x10)),
+                   "aes::soft::fixslice::aes128_encrypt+0x10 at src/soft/fixslice64.rs:120");
+
+        let unknown = Location { symbol: None, offset: 0x10, file: None, line: None, object: Some("libc.so.6".to_string()) };
+        assert_eq!(unknown.source(), "libc.so.6");
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::access;
+use crate::access::{AccessKind, StringOp};
+use crate::decoder::{Instruction, Memory, OperandKind, Register};
+use crate::latency::LatencyPolicy;
+use crate::symbols::Symbolizer;
+use crate::tracer::effective_address;
+
+/// A way in which secret data influenced something observable.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LeakKind {
+    /// A conditional branch on flags computed from secret data, or an indirect
+    /// branch to a secret dependent target.
+    Branch,
+    /// A memory access whose address was computed from secret data.
+    Address,
+    /// A REP string instruction with a secret dependent count.
+    RepCount,
+    /// A variable latency instruction with secret operands.
+    VariableLatency
+}
+
+// Registers are tracked as a whole: the 16 general purpose registers (whatever
+// their width) followed by the 16 vector registers. Writing the low 8 or 16 bits
+// of a general purpose register leaves the rest of it as it was, so only adds
+// taint; 32 and 64-bit writes replace all of it.
+const NUM_SLOTS: usize = 32;
+
+fn slot(reg: Register) -> Option<usize> {
+    match reg {
+        Register::Gpr { num, .. } => Some(num as usize),
+        Register::Vector(n) if n < 16 => Some(16 + n as usize),
+        _ => None
+    }
+}
+
+const RAX: usize = 0;
+const RCX: usize = 1;
+const RDX: usize = 2;
+const RSP: usize = 4;
+const RBP: usize = 5;
+const RSI: usize = 6;
+const RDI: usize = 7;
+
+// Instructions whose result is zero regardless of the input when both inputs are
+// the same register: the two operands, or the two sources of the three operand
+// VEX forms.
+static ZERO_IDIOMS: &[&str] = &[
+    "XOR", "SUB", "PXOR", "XORPS", "XORPD", "VPXOR", "VXORPS", "VXORPD", "PSUBB", "PSUBD", "PSUBQ"
+];
+
+// Instructions that read the flags as an input.
+fn reads_flags(mnemonic: &str) -> bool {
+    mnemonic.starts_with("CMOV") || mnemonic.starts_with("SET") ||
+        ["ADC", "SBB", "RCL", "RCR", "PUSHF", "PUSHFD", "PUSHFQ", "LAHF"].contains(&mnemonic)
+}
+
+fn partial_write(reg: Register) -> bool {
+    matches!(reg, Register::Gpr { size, .. } if size < 4)
+}
+
+fn is_conditional_branch(mnemonic: &str) -> bool {
+    (mnemonic.starts_with('J') && !mnemonic.starts_with("JMP")) || mnemonic.starts_with("LOOP")
+}
+
+/// Which registers, flags and bytes of memory currently hold data derived from a
+/// secret.
+#[derive(Default)]
+pub struct TaintState {
+    regs: [bool; NUM_SLOTS],
+    flags: bool,
+    memory: HashSet<u64>
+}
+
+impl TaintState {
+    pub fn new() -> TaintState {
+        TaintState {
+            regs: [false; NUM_SLOTS],
+            flags: false,
+            memory: HashSet::new()
+        }
+    }
+
+    pub fn taint_memory(&mut self, addr: u64, len: u64) {
+        self.set_mem(addr, len, true);
+    }
+
+    fn slot_tainted(&self, slot: usize) -> bool {
+        self.regs[slot]
+    }
+
+    fn reg(&self, reg: Register) -> bool {
+        match slot(reg) {
+            Some(slot) => self.regs[slot],
+            None => false
+        }
+    }
+
+    fn set_reg(&mut self, reg: Register, tainted: bool) {
+        if let Some(slot) = slot(reg) {
+            self.set_slot(slot, partial_write(reg), tainted);
+        }
+    }
+
+    fn set_slot(&mut self, slot: usize, partial: bool, tainted: bool) {
+        if partial {
+            self.regs[slot] |= tainted;
+        } else {
+            self.regs[slot] = tainted;
+        }
+    }
+
+    fn mem(&self, addr: u64, size: u64) -> bool {
+        (0..size).any(|i| self.memory.contains(&addr.wrapping_add(i)))
+    }
+
+    fn set_mem(&mut self, addr: u64, size: u64, tainted: bool) {
+        for i in 0..size {
+            if tainted {
+                self.memory.insert(addr.wrapping_add(i));
+            } else {
+                self.memory.remove(&addr.wrapping_add(i));
+            }
+        }
+    }
+
+    // Whether the registers used to compute a memory operand's address are tainted.
+    fn address_tainted(&self, mem: &Memory) -> bool {
+        mem.base.is_some_and(|reg| self.reg(reg)) || mem.index.is_some_and(|reg| self.reg(reg))
+    }
+
+    /// Propagate taint through one instruction, about to be executed with the given
+    /// registers, and return any leaks it causes.
+    pub fn step(&mut self, regs: &libc::user_regs_struct, instruction: &Instruction,
+                latency: Option<&LatencyPolicy>) -> Vec<LeakKind> {
+        let mut leaks = Vec::new();
+
+        if let Some(string) = access::string_instruction(instruction) {
+            self.step_string(regs, &string, &mut leaks);
+            return leaks;
+        }
+        let mnemonic = instruction.mnemonic.as_str();
+
+        // The one operand forms of multiply and divide use RDX:RAX implicitly; the
+        // two and three operand forms of IMUL are ordinary.
+        let operand_count = instruction.operands.len();
+        let implicit_rdx_rax = operand_count == 1 && ["MUL", "IMUL", "DIV", "IDIV"].contains(&mnemonic);
+
+        let op0_kind = access::classify(mnemonic, 0);
+        let (op0_read, op0_written) = match mnemonic {
+            "LEA" => (false, true),
+            "NOP" => (false, false),
+            "IMUL" if operand_count == 2 => (true, true),
+            "IMUL" if operand_count == 3 => (false, true),
+            _ => (op0_kind != Some(AccessKind::Store), op0_kind != Some(AccessKind::Load))
+        };
+
+        // Gather the taint of everything the instruction reads.
+        let mut src = reads_flags(mnemonic) && self.flags;
+        let mut dest_mem = None;
+        for (i, op) in instruction.operands.iter().enumerate() {
+            let read = i > 0 || op0_read;
+            match op.kind {
+                OperandKind::Register(reg) if read => src |= self.reg(reg),
+                OperandKind::Memory(ref mem) => {
+                    let address_tainted = self.address_tainted(mem);
+                    if mnemonic == "LEA" {
+                        src |= address_tainted;
+                        continue;
+                    }
+                    if mnemonic == "NOP" {
+                        continue;
+                    }
+                    if address_tainted {
+                        leaks.push(LeakKind::Address);
+                    }
+                    let addr = effective_address(regs, mem);
+                    if read {
+                        src |= self.mem(addr, op.size as u64);
+                    }
+                    if i == 0 {
+                        dest_mem = Some((addr, op.size as u64));
+                    }
+                }
+                _ => { }
+            }
+        }
+
+        let same_registers = match instruction.operands.as_slice() {
+            [a, b] | [_, a, b] => matches!((a.kind, b.kind), (OperandKind::Register(a), OperandKind::Register(b)) if a == b),
+            _ => false
+        };
+        if ZERO_IDIOMS.contains(&mnemonic) && same_registers {
+            src = false;
+        }
+
+        // Branches: conditional ones on the flags (or RCX), indirect ones on their
+        // target.
+        if is_conditional_branch(mnemonic) {
+            let tainted = match mnemonic {
+                "JRCXZ" | "JECXZ" => self.slot_tainted(RCX),
+                m if m.starts_with("LOOP") => self.slot_tainted(RCX) || (m != "LOOP" && self.flags),
+                _ => self.flags
+            };
+            if tainted {
+                leaks.push(LeakKind::Branch);
+            }
+            return leaks;
+        }
+
+        match mnemonic {
+            "JMP" | "CALL" => {
+                if src {
+                    leaks.push(LeakKind::Branch);
+                }
+                if mnemonic == "CALL" {
+                    self.set_mem(regs.rsp.wrapping_sub(8), 8, false);
+                }
+                return leaks;
+            }
+            "RET" => {
+                if self.mem(regs.rsp, 8) {
+                    leaks.push(LeakKind::Branch);
+                }
+                return leaks;
+            }
+            "PUSH" | "PUSHF" | "PUSHFQ" => {
+                self.set_mem(regs.rsp.wrapping_sub(8), 8, src);
+                return leaks;
+            }
+            "POP" | "POPF" | "POPFQ" => src = self.mem(regs.rsp, 8),
+            "LEAVE" => {
+                let tainted = self.mem(regs.rbp, 8);
+                self.regs[RSP] = self.regs[RBP];
+                self.regs[RBP] = tainted;
+                return leaks;
+            }
+            "CDQ" | "CQO" => {
+                self.regs[RDX] = self.regs[RAX];
+                return leaks;
+            }
+            "CWD" => {
+                self.regs[RDX] |= self.regs[RAX];
+                return leaks;
+            }
+            _ => { }
+        }
+
+        // The byte forms use AX alone, the wider ones (E|R)DX:(E|R)AX.
+        let implicit_size = instruction.operands.first().map(|op| op.size).unwrap_or(0);
+        if implicit_rdx_rax {
+            src |= self.regs[RAX];
+            if (mnemonic == "DIV" || mnemonic == "IDIV") && implicit_size > 1 {
+                src |= self.regs[RDX];
+            }
+        }
+
+        if let Some(policy) = latency {
+            if src && policy.is_variable(mnemonic) {
+                leaks.push(LeakKind::VariableLatency);
+            }
+        }
+
+        if implicit_rdx_rax {
+            self.set_slot(RAX, implicit_size < 4, src);
+            if implicit_size > 1 {
+                self.set_slot(RDX, implicit_size < 4, src);
+            }
+        } else if mnemonic == "XCHG" {
+            // Approximate: both operands end up with the union of their taint.
+            for op in instruction.operands.iter().take(2) {
+                match op.kind {
+                    OperandKind::Register(reg) => self.set_reg(reg, src),
+                    OperandKind::Memory(ref mem) => {
+                        let addr = effective_address(regs, mem);
+                        self.set_mem(addr, op.size as u64, src);
+                    }
+                    _ => { }
+                }
+            }
+        } else if op0_written {
+            match instruction.operands.first().map(|op| op.kind) {
+                Some(OperandKind::Register(reg)) => self.set_reg(reg, src),
+                _ => if let Some((addr, size)) = dest_mem {
+                    self.set_mem(addr, size, src);
+                }
+            }
+        }
+
+        if instruction.writes_flags {
+            self.flags = src;
+        }
+
+        leaks
+    }
+
+    fn step_string(&mut self, regs: &libc::user_regs_struct, string: &access::StringInstruction,
+                   leaks: &mut Vec<LeakKind>) {
+        if string.rep && self.regs[RCX] {
+            leaks.push(LeakKind::RepCount);
+        }
+        let uses_rsi = matches!(string.op, StringOp::Movs | StringOp::Lods | StringOp::Cmps);
+        let uses_rdi = string.op != StringOp::Lods;
+        if (uses_rsi && self.regs[RSI]) || (uses_rdi && self.regs[RDI]) {
+            leaks.push(LeakKind::Address);
+        }
+        if string.rep && regs.rcx == 0 {
+            return;
+        }
+        let size = string.size as u64;
+        match string.op {
+            StringOp::Movs => {
+                let tainted = self.mem(string.source(regs), size);
+                self.set_mem(regs.rdi, size, tainted);
+            }
+            StringOp::Stos => {
+                let tainted = self.regs[RAX];
+                self.set_mem(regs.rdi, size, tainted);
+            }
+            StringOp::Lods => {
+                let tainted = self.mem(string.source(regs), size);
+                self.set_slot(RAX, size < 4, tainted);
+            }
+            StringOp::Cmps => self.flags = self.mem(string.source(regs), size) || self.mem(regs.rdi, size),
+            StringOp::Scas => self.flags = self.regs[RAX] || self.mem(regs.rdi, size)
+        }
+    }
+}
+
+/// Leaks found so far, by address and kind.
+#[derive(Default)]
+pub struct Leaks {
+    seen: HashMap<(u64, LeakKind), (String, usize)>
+}
+
+impl Leaks {
+    pub fn new() -> Leaks {
+        Leaks { seen: HashMap::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn record(&mut self, rip: u64, kind: LeakKind, mnemonic: &str) {
+        if let Some(entry) = self.seen.get_mut(&(rip, kind)) {
+            entry.1 += 1;
+            return;
+        }
+        self.seen.insert((rip, kind), (mnemonic.to_string(), 1));
+    }
+
+    pub fn print(&self, symbols: &Symbolizer) {
+        let mut keys: Vec<&(u64, LeakKind)> = self.seen.keys().collect();
+        keys.sort_by_key(|a| a.0);
+        println!("Secret dependent behaviour:");
+        for key in keys {
+            let (rip, kind) = *key;
+            let (ref mnemonic, count) = self.seen[key];
+            let what = match kind {
+                LeakKind::Branch => "branch on secret",
+                LeakKind::Address => "secret dependent address",
+                LeakKind::RepCount => "secret dependent repeat count",
+                LeakKind::VariableLatency => "variable latency on secret"
+            };
+            println!("    {:016x} {:<8} x{:<6} {:<30} {}", rip, mnemonic, count, what, symbols.describe(rip));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder;
+    use crate::ptrace;
+
+    fn step_with(taint: &mut TaintState, regs: &libc::user_regs_struct, code: &[u8]) -> Vec<LeakKind> {
+        let instruction = decoder::decode(0, code).unwrap();
+        taint.step(regs, &instruction, None)
+    }
+
+    fn step(taint: &mut TaintState, code: &[u8]) -> Vec<LeakKind> {
+        step_with(taint, &ptrace::zeroed_regs(), code)
+    }
+
+    fn tainted(slots: &[usize]) -> TaintState {
+        let mut taint = TaintState::new();
+        for &slot in slots {
+            taint.regs[slot] = true;
+        }
+        taint
+    }
+
+    #[test]
+    fn zero_idiom() {
+        let mut taint = tainted(&[RAX]);
+        step(&mut taint, &[0x31, 0xc0]); // XOR EAX, EAX
+        assert!(!taint.regs[RAX]);
+        assert!(!taint.flags);
+
+        let mut taint = tainted(&[RCX]);
+        step(&mut taint, &[0x31, 0xc8]); // XOR EAX, ECX
+        assert!(taint.regs[RAX]);
+        assert!(taint.flags);
+    }
+
+    #[test]
+    fn vex_zero_idiom() {
+        // The destination of the three operand form isn't an input.
+        let mut taint = tainted(&[16]);
+        step(&mut taint, &[0xc5, 0xf1, 0xef, 0xc1]); // VPXOR XMM0, XMM1, XMM1
+        assert!(!taint.regs[16]);
+
+        let mut taint = tainted(&[17]);
+        step(&mut taint, &[0xc5, 0xf9, 0xef, 0xc1]); // VPXOR XMM0, XMM0, XMM1
+        assert!(taint.regs[16]);
+
+        let mut taint = tainted(&[16]);
+        step(&mut taint, &[0x66, 0x0f, 0xef, 0xc0]); // PXOR XMM0, XMM0
+        assert!(!taint.regs[16]);
+
+        let mut taint = tainted(&[17]);
+        step(&mut taint, &[0x66, 0x0f, 0xef, 0xc1]); // PXOR XMM0, XMM1
+        assert!(taint.regs[16]);
+    }
+
+    #[test]
+    fn partial_writes() {
+        // Only the low byte or word is replaced, so the rest stays tainted.
+        let mut taint = tainted(&[RAX]);
+        step(&mut taint, &[0x88, 0xc8]); // MOV AL, CL
+        assert!(taint.regs[RAX]);
+        step(&mut taint, &[0x66, 0x89, 0xc8]); // MOV AX, CX
+        assert!(taint.regs[RAX]);
+        step(&mut taint, &[0x30, 0xc0]); // XOR AL, AL
+        assert!(taint.regs[RAX]);
+        // A 32-bit write zero extends.
+        step(&mut taint, &[0x89, 0xc8]); // MOV EAX, ECX
+        assert!(!taint.regs[RAX]);
+
+        let mut taint = tainted(&[RCX]);
+        step(&mut taint, &[0x88, 0xec]); // MOV AH, CH
+        assert!(taint.regs[RAX]);
+    }
+
+    #[test]
+    fn cmov_reads_flags() {
+        let mut taint = TaintState::new();
+        taint.flags = true;
+        let leaks = step(&mut taint, &[0x0f, 0x44, 0xc1]); // CMOVE EAX, ECX
+        assert!(leaks.is_empty());
+        assert!(taint.regs[RAX]);
+
+        let mut taint = TaintState::new();
+        step(&mut taint, &[0x0f, 0x44, 0xc1]);
+        assert!(!taint.regs[RAX]);
+    }
+
+    #[test]
+    fn flags_through_the_stack() {
+        let mut taint = TaintState::new();
+        taint.flags = true;
+        let mut regs = ptrace::zeroed_regs();
+        regs.rsp = 0x1000;
+        step_with(&mut taint, &regs, &[0x9c]); // PUSHFQ
+        regs.rsp -= 8;
+        step_with(&mut taint, &regs, &[0x58]); // POP RAX
+        assert!(taint.regs[RAX]);
+    }
+
+    #[test]
+    fn branch_on_tainted_flags() {
+        let mut taint = tainted(&[RAX]);
+        step(&mut taint, &[0x39, 0xc8]); // CMP EAX, ECX
+        assert!(taint.flags);
+        assert_eq!(step(&mut taint, &[0x74, 0x00]), vec![LeakKind::Branch]); // JE
+
+        let mut taint = tainted(&[RDX]);
+        step(&mut taint, &[0x39, 0xc8]);
+        assert!(!taint.flags);
+        assert!(step(&mut taint, &[0x74, 0x00]).is_empty());
+    }
+
+    #[test]
+    fn large_memory_taint() {
+        let mut taint = TaintState::new();
+        taint.taint_memory(0x10000, 0x20000);
+        let mut regs = ptrace::zeroed_regs();
+        regs.rsi = 0x2fffc;
+        step_with(&mut taint, &regs, &[0x8b, 0x06]); // MOV EAX, [RSI]
+        assert!(taint.regs[RAX]);
+    }
+}
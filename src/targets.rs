@@ -0,0 +1,213 @@
+use core::arch::asm;
+use std::hint::black_box;
+
+use aes::cipher::consts::U16;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockEncrypt, KeyInit, StreamCipher};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256 as Sha256Hasher};
+
+use crate::ConstantTimeTarget;
+use crate::inputs::InputClass;
+
+#[inline(never)]
+fn doaes(key: &[u8], data: &[u8]) {
+    unsafe { asm!("nop") };
+    let cipher = aes::Aes128::new(GenericArray::from_slice(key));
+    let mut result = GenericArray::clone_from_slice(data);
+    cipher.encrypt_block(&mut result);
+    black_box(&mut result);
+    unsafe { asm!("nop") };
+}
+
+#[inline(never)]
+fn dorc4(key: &[u8], data: &[u8]) {
+    unsafe { asm!("nop") };
+    let mut cipher = rc4::Rc4::<U16>::new(GenericArray::from_slice(key));
+    let mut result = GenericArray::<u8, U16>::clone_from_slice(data);
+    cipher.apply_keystream(&mut result);
+    black_box(&mut result);
+    unsafe { asm!("nop") };
+}
+
+#[inline(never)]
+fn dohash(data: &[u8]) {
+    unsafe { asm!("nop") };
+    let mut hasher = Sha256Hasher::new();
+    hasher.update(data);
+    let mut result = hasher.finalize();
+    black_box(&mut result);
+    unsafe { asm!("nop") };
+}
+
+#[inline(never)]
+fn doeq(a: &[u8], b: &[u8]) {
+    unsafe { asm!("nop") };
+    black_box(constant_time_eq::constant_time_eq(a, b));
+    unsafe { asm!("nop") };
+}
+
+fn random_bytes(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    rng.fill_bytes(&mut buf);
+    buf
+}
+
+/// AES-128 encryption of a single block using the `aes` crate.
+///
+/// The crate uses AES-NI when the CPU has it unless it is built with `--cfg
+/// aes_force_soft`, which `.cargo/config.toml` passes when this workspace is built
+/// without RUSTFLAGS set. Other builds may trace the hardware instructions rather
+/// than the software implementation, which `setup` warns about.
+pub struct Aes128;
+
+impl ConstantTimeTarget for Aes128 {
+    fn name(&self) -> &str { "aes" }
+
+    fn setup(&mut self) {
+        if !cfg!(aes_force_soft) && std::arch::is_x86_feature_detected!("aes") {
+            println!("Warning: aes was built without --cfg aes_force_soft, so AES-NI is traced");
+        }
+        doaes(&[0u8; 16], &[0u8; 16]);
+    }
+
+    fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        vec![random_bytes(rng, 16), random_bytes(rng, 16)]
+    }
+
+    fn input_classes(&self) -> Vec<InputClass> {
+        InputClass::all()
+    }
+
+    fn secret_inputs(&self) -> Vec<usize> {
+        vec![0]
+    }
+
+    fn run(&mut self, inputs: &[Vec<u8>]) {
+        doaes(&inputs[0], &inputs[1]);
+    }
+}
+
+/// RC4 from the `rc4` crate, keyed with a 16 byte key, processing 16 bytes of
+/// data.
+pub struct Rc4;
+
+impl ConstantTimeTarget for Rc4 {
+    fn name(&self) -> &str { "rc4" }
+
+    fn setup(&mut self) {
+        dorc4(&[0u8; 16], &[0u8; 16]);
+    }
+
+    fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        vec![random_bytes(rng, 16), random_bytes(rng, 16)]
+    }
+
+    fn input_classes(&self) -> Vec<InputClass> {
+        InputClass::all()
+    }
+
+    fn secret_inputs(&self) -> Vec<usize> {
+        vec![0]
+    }
+
+    fn run(&mut self, inputs: &[Vec<u8>]) {
+        dorc4(&inputs[0], &inputs[1]);
+    }
+}
+
+/// SHA-256 of a 4 byte message.
+pub struct Sha256;
+
+impl ConstantTimeTarget for Sha256 {
+    fn name(&self) -> &str { "sha256" }
+
+    fn setup(&mut self) {
+        dohash(&[0u8; 4]);
+    }
+
+    fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        vec![random_bytes(rng, 4)]
+    }
+
+    fn input_classes(&self) -> Vec<InputClass> {
+        InputClass::all()
+    }
+
+    fn secret_inputs(&self) -> Vec<usize> {
+        vec![0]
+    }
+
+    fn run(&mut self, inputs: &[Vec<u8>]) {
+        dohash(&inputs[0]);
+    }
+}
+
+/// `constant_time_eq::constant_time_eq` on two 4 byte buffers that are equal about
+/// half of the time.
+pub struct FixedTimeEq;
+
+impl ConstantTimeTarget for FixedTimeEq {
+    fn name(&self) -> &str { "fixed_time_eq" }
+
+    fn setup(&mut self) {
+        doeq(&[0u8; 4], &[0u8; 4]);
+    }
+
+    fn generate_inputs(&mut self, rng: &mut StdRng) -> Vec<Vec<u8>> {
+        let a = random_bytes(rng, 4);
+        let mut b = a.clone();
+        if rng.gen() {
+            let i = rng.gen_range(0..4);
+            b[i] ^= 1 << rng.gen_range(0..8);
+        }
+        vec![a, b]
+    }
+
+    fn input_classes(&self) -> Vec<InputClass> {
+        InputClass::all()
+    }
+
+    fn secret_inputs(&self) -> Vec<usize> {
+        vec![0, 1]
+    }
+
+    // Trace the library's function itself rather than the wrapper around it.
+    fn traced_function(&self) -> Option<&str> {
+        Some("constant_time_eq::constant_time_eq")
+    }
+
+    fn run(&mut self, inputs: &[Vec<u8>]) {
+        doeq(&inputs[0], &inputs[1]);
+    }
+}
+
+/// All of the targets that ship with the tester.
+pub fn builtin() -> Vec<Box<dyn ConstantTimeTarget>> {
+    vec![
+        Box::new(Aes128),
+        Box::new(Rc4),
+        Box::new(Sha256),
+        Box::new(FixedTimeEq)
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trace_target, Config};
+
+    // The defaults compare absolute addresses, so this fails if an input ends up
+    // somewhere else from one run to the next. RC4 indexes its state with key
+    // bytes and should be caught. Two iterations cover both halves of the classes
+    // that alternate.
+    #[test]
+    fn builtin_targets_with_default_config() {
+        let config = Config { iterations: 2, ..Config::default() };
+        for mut target in builtin() {
+            let expected = target.name() != "rc4";
+            assert_eq!(trace_target(&mut *target, &config), expected, "{}", target.name());
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use core::arch::asm;
+use std::cmp;
+use std::hint::black_box;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::ConstantTimeTarget;
+use crate::Config;
+
+/// Measurements taken between recomputing nothing but the t statistics. Inputs
+/// for a whole batch are generated up front so that generating them isn't timed.
+const BATCH_SIZE: usize = 10000;
+
+/// Number of cropped tests, each discarding measurements above a progressively
+/// lower percentile of the calibration measurements.
+const NUM_PERCENTILES: usize = 10;
+
+/// The fewest measurements timing mode will take. A tenth of them, up to a whole
+/// batch, only calibrate the cutoffs.
+pub const MIN_MEASUREMENTS: usize = 100;
+
+/// Read the time stamp counter, serialized so that earlier instructions can't be
+/// reordered past it.
+#[inline(always)]
+fn cycles_begin() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    unsafe {
+        asm!("lfence", "rdtsc", out("eax") lo, out("edx") hi, options(nostack));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Read the time stamp counter once everything before it has finished.
+#[inline(always)]
+fn cycles_end() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    unsafe {
+        asm!("rdtscp", "lfence", out("eax") lo, out("edx") hi, out("ecx") _, options(nostack));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Welch's t-test over two classes, updated one sample at a time using Welford's
+/// method for the mean and variance.
+#[derive(Copy, Clone, Default)]
+pub struct WelchTest {
+    n: [f64; 2],
+    mean: [f64; 2],
+    m2: [f64; 2]
+}
+
+impl WelchTest {
+    pub fn new() -> WelchTest {
+        WelchTest { n: [0.0; 2], mean: [0.0; 2], m2: [0.0; 2] }
+    }
+
+    pub fn push(&mut self, class: usize, x: f64) {
+        self.n[class] += 1.0;
+        let delta = x - self.mean[class];
+        self.mean[class] += delta / self.n[class];
+        self.m2[class] += delta * (x - self.mean[class]);
+    }
+
+    pub fn samples(&self) -> f64 {
+        self.n[0] + self.n[1]
+    }
+
+    /// The t statistic, or 0 until both classes have enough samples. Classes that
+    /// each always take the same time but differ from each other, which a coarse
+    /// timer can produce, are perfectly separated and give an infinite t.
+    pub fn t(&self) -> f64 {
+        if self.n[0] < 2.0 || self.n[1] < 2.0 {
+            return 0.0;
+        }
+        let var0 = self.m2[0] / (self.n[0] - 1.0);
+        let var1 = self.m2[1] / (self.n[1] - 1.0);
+        let denominator = (var0 / self.n[0] + var1 / self.n[1]).sqrt();
+        let difference = self.mean[0] - self.mean[1];
+        if denominator == 0.0 {
+            return if difference == 0.0 { 0.0 } else { f64::INFINITY.copysign(difference) };
+        }
+        difference / denominator
+    }
+}
+
+// The cutoffs used by dudect: percentiles that get exponentially closer to 1 so
+// that the cropped tests focus on the fast, low noise end of the distribution.
+fn percentile_cutoffs(measurements: &[u64]) -> Vec<u64> {
+    let mut sorted = measurements.to_vec();
+    sorted.sort_unstable();
+    (0..NUM_PERCENTILES).map(|i| {
+        let p = 1.0 - 0.5f64.powf(10.0 * (i + 1) as f64 / NUM_PERCENTILES as f64);
+        sorted[((sorted.len() - 1) as f64 * p) as usize]
+    }).collect()
+}
+
+/// Run `target` natively `config.measurements` times, alternating at random
+/// between one fixed input (class 0) and fresh random inputs (class 1), and test
+/// whether the two classes take a different number of cycles.
+///
+/// The largest |t| over the uncropped and cropped tests is reported; anything
+/// above `config.t_threshold` is taken as evidence of a timing leak.
+pub fn timing_test(target: &mut dyn ConstantTimeTarget, config: &Config) -> bool {
+    if config.measurements < MIN_MEASUREMENTS {
+        panic!("Timing mode needs at least {} measurements, not {}", MIN_MEASUREMENTS, config.measurements);
+    }
+    let calibration = cmp::min(BATCH_SIZE, config.measurements / 10);
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    target.setup();
+    let fixed = target.generate_inputs(&mut rng);
+
+    let mut tests = vec![WelchTest::new(); NUM_PERCENTILES + 1];
+    let mut cutoffs: Option<Vec<u64>> = None;
+    let mut done = 0;
+    while done < config.measurements {
+        let size = if cutoffs.is_none() { calibration } else { BATCH_SIZE };
+        let batch = cmp::min(size, config.measurements - done);
+
+        let mut classes = Vec::with_capacity(batch);
+        let mut inputs = Vec::with_capacity(batch);
+        for _ in 0..batch {
+            let class = if rng.gen() { 0 } else { 1 };
+            classes.push(class);
+            inputs.push(if class == 0 { fixed.clone() } else { target.generate_inputs(&mut rng) });
+        }
+
+        // (class, cycles) of each measurement. One that appears to have gone
+        // backwards (the thread moved to a CPU whose counter is behind) is dropped.
+        let mut measured = Vec::with_capacity(batch);
+        for (&class, input) in classes.iter().zip(inputs.iter_mut()) {
+            black_box(&mut *input);
+            let start = cycles_begin();
+            target.run(input);
+            let end = cycles_end();
+            if end >= start {
+                measured.push((class, end.wrapping_sub(start)));
+            }
+        }
+        done += batch;
+
+        // The first batch only calibrates the cutoffs, and is otherwise discarded
+        // along with any warm up effects.
+        if cutoffs.is_none() {
+            if !measured.is_empty() {
+                let cycles: Vec<u64> = measured.iter().map(|&(_, c)| c).collect();
+                cutoffs = Some(percentile_cutoffs(&cycles));
+            }
+            continue;
+        }
+        let cutoffs = cutoffs.as_ref().unwrap();
+
+        for &(class, c) in measured.iter() {
+            tests[0].push(class, c as f64);
+            for (i, &cutoff) in cutoffs.iter().enumerate() {
+                if c < cutoff {
+                    tests[i + 1].push(class, c as f64);
+                }
+            }
+        }
+    }
+
+    let mut max_t = 0.0f64;
+    let mut samples = 0.0f64;
+    for test in &tests {
+        if test.t().abs() > max_t.abs() {
+            max_t = test.t();
+            samples = test.samples();
+        }
+    }
+
+    let passed = max_t.abs() <= config.t_threshold;
+    println!("{} measurements, max |t| = {:.2} over {} samples (threshold {:.1}): {}",
+             done, max_t.abs(), samples, config.t_threshold,
+             if passed { "no timing leak detected" } else { "timing leak detected" });
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn welch(a: &[f64], b: &[f64]) -> WelchTest {
+        let mut test = WelchTest::new();
+        for &x in a {
+            test.push(0, x);
+        }
+        for &x in b {
+            test.push(1, x);
+        }
+        test
+    }
+
+    #[test]
+    fn t_statistic() {
+        // Means 3 and 6, variances 2.5 and 10.
+        let test = welch(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 4.0, 6.0, 8.0, 10.0]);
+        assert_eq!(test.samples(), 10.0);
+        assert!((test.t() - -1.8973665961).abs() < 1e-9);
+
+        // Unequal class sizes: means 12 and 2.5, variances 4 and 5/3.
+        let test = welch(&[10.0, 12.0, 14.0], &[1.0, 2.0, 3.0, 4.0]);
+        assert!((test.t() - 7.1813249871).abs() < 1e-9);
+    }
+
+    #[test]
+    fn t_needs_two_samples() {
+        assert_eq!(welch(&[1.0], &[2.0, 3.0]).t(), 0.0);
+    }
+
+    #[test]
+    fn t_without_variance() {
+        assert_eq!(welch(&[1.0, 1.0], &[1.0, 1.0]).t(), 0.0);
+        assert_eq!(welch(&[1.0, 1.0], &[2.0, 2.0]).t(), f64::NEG_INFINITY);
+        assert_eq!(welch(&[3.0, 3.0, 3.0], &[2.0, 2.0]).t(), f64::INFINITY);
+    }
+
+    #[test]
+    fn cutoffs_are_increasing_percentiles() {
+        let measurements: Vec<u64> = (0..1000).rev().collect();
+        let cutoffs = percentile_cutoffs(&measurements);
+        assert_eq!(cutoffs.len(), NUM_PERCENTILES);
+        assert_eq!(cutoffs[0], 499);
+        assert!(cutoffs.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(cutoffs[NUM_PERCENTILES - 1], 998);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::elf::Reader;
+use crate::access::{AccessKind, MemAccess};
+use crate::inputs::InputClass;
+use crate::maps::Mapping;
+use crate::tracer::{Step, Trace};
+
+// A trace file starts with a header describing the run (target, input class,
+// inputs and where they were, the build id of the traced executable and its
+// memory map), followed by the instruction bytes of every distinct address that
+// was executed, the sequence of instruction addresses, and the memory accesses of
+// each step. Addresses are stored as zigzag encoded deltas from the previous one
+// in LEB128, so straight line code and sequential accesses take a byte or two per
+// step.
+const MAGIC: &[u8] = b"FTTTRACE";
+const VERSION: u32 = 2;
+
+/// What a trace file records about the run besides the trace itself.
+pub struct Header {
+    pub target: String,
+    /// The GNU build id of the traced executable, if it has one.
+    pub build_id: Option<Vec<u8>>
+}
+
+fn invalid(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid trace file: {}", detail))
+}
+
+fn zigzag(delta: i64) -> u64 {
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn put_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn put_delta(out: &mut Vec<u8>, last: &mut u64, value: u64) {
+    put_uleb128(out, zigzag(value.wrapping_sub(*last) as i64));
+    *last = value;
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_uleb128(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn get_delta(r: &mut Reader, last: &mut u64) -> u64 {
+    *last = last.wrapping_add(unzigzag(r.uleb128()) as u64);
+    *last
+}
+
+fn get_bytes<'a>(r: &mut Reader<'a>, data: &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = r.uleb128() as usize;
+    if r.pos > data.len() || len > data.len() - r.pos {
+        return Err(invalid("truncated".to_string()));
+    }
+    let bytes = &data[r.pos..r.pos + len];
+    r.skip(len);
+    Ok(bytes)
+}
+
+fn get_string<'a>(r: &mut Reader<'a>, data: &'a [u8]) -> io::Result<String> {
+    let bytes = get_bytes(r, data)?;
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(s) => Ok(s),
+        Err(_) => Err(invalid("bad string".to_string()))
+    }
+}
+
+// Counts are read before the things they count, so check they could actually fit
+// in what's left of the file before allocating for them.
+fn get_count(r: &mut Reader, data: &[u8]) -> io::Result<usize> {
+    let count = r.uleb128();
+    if r.pos > data.len() || count > (data.len() - r.pos) as u64 {
+        return Err(invalid("truncated".to_string()));
+    }
+    Ok(count as usize)
+}
+
+fn kind_byte(kind: AccessKind) -> u8 {
+    match kind {
+        AccessKind::Load => 0,
+        AccessKind::Store => 1,
+        AccessKind::LoadStore => 2
+    }
+}
+
+/// Encode `trace` into the trace file format.
+pub fn encode(header: &Header, trace: &Trace) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    for i in 0..4 {
+        out.push((VERSION >> (8 * i)) as u8);
+    }
+
+    put_bytes(&mut out, header.target.as_bytes());
+    put_bytes(&mut out, trace.class.name().as_bytes());
+    match header.build_id {
+        Some(ref id) => put_bytes(&mut out, id),
+        None => put_bytes(&mut out, &[])
+    }
+
+    put_uleb128(&mut out, trace.inputs.len() as u64);
+    put_uleb128(&mut out, trace.inputs_addr);
+    for (input, &addr) in trace.inputs.iter().zip(trace.input_addrs.iter()) {
+        put_uleb128(&mut out, addr);
+        put_bytes(&mut out, input);
+    }
+
+    put_uleb128(&mut out, trace.maps.len() as u64);
+    for mapping in trace.maps.iter() {
+        put_uleb128(&mut out, mapping.start);
+        put_uleb128(&mut out, mapping.end - mapping.start);
+        put_uleb128(&mut out, mapping.offset);
+        put_bytes(&mut out, mapping.perms.as_bytes());
+        match mapping.path {
+            Some(ref path) => put_bytes(&mut out, path.as_bytes()),
+            None => put_bytes(&mut out, &[])
+        }
+    }
+
+    // The instruction bytes are stored once per address rather than per step.
+    let mut code: Vec<&Step> = Vec::new();
+    {
+        let mut seen = HashSet::new();
+        for step in trace.steps.iter() {
+            if seen.insert(step.rip) {
+                code.push(step);
+            }
+        }
+    }
+    code.sort_by_key(|a| a.rip);
+    put_uleb128(&mut out, code.len() as u64);
+    let mut last = 0u64;
+    for step in code.iter() {
+        put_delta(&mut out, &mut last, step.rip);
+        put_bytes(&mut out, step.code());
+    }
+
+    put_uleb128(&mut out, trace.steps.len() as u64);
+    let mut last = 0u64;
+    for step in trace.steps.iter() {
+        put_delta(&mut out, &mut last, step.rip);
+    }
+    let mut last = 0u64;
+    for step in trace.steps.iter() {
+        put_uleb128(&mut out, step.mem_accesses.len() as u64);
+        for access in step.mem_accesses.iter() {
+            put_delta(&mut out, &mut last, access.addr);
+            put_uleb128(&mut out, access.size as u64);
+            out.push(kind_byte(access.kind));
+        }
+    }
+
+    out
+}
+
+/// Decode a trace file.
+pub fn decode(data: &[u8]) -> io::Result<(Header, Trace)> {
+    if data.len() < MAGIC.len() + 4 || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a trace file".to_string()));
+    }
+    let mut r = Reader::at(data, MAGIC.len());
+    let version = r.u32();
+    if version != VERSION {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+
+    let target = get_string(&mut r, data)?;
+    let class_name = get_string(&mut r, data)?;
+    let class = match InputClass::parse(&class_name) {
+        Some(class) => class,
+        None => return Err(invalid(format!("unknown input class {}", class_name)))
+    };
+    let build_id = get_bytes(&mut r, data)?;
+    let build_id = if build_id.is_empty() { None } else { Some(build_id.to_vec()) };
+
+    let mut trace = Trace {
+        class,
+        inputs: Vec::new(),
+        input_addrs: Vec::new(),
+        inputs_addr: 0,
+        steps: Vec::new(),
+        maps: Vec::new()
+    };
+
+    let input_count = get_count(&mut r, data)?;
+    trace.inputs_addr = r.uleb128();
+    for _ in 0..input_count {
+        trace.input_addrs.push(r.uleb128());
+        trace.inputs.push(get_bytes(&mut r, data)?.to_vec());
+    }
+
+    for _ in 0..get_count(&mut r, data)? {
+        let start = r.uleb128();
+        let end = match start.checked_add(r.uleb128()) {
+            Some(end) => end,
+            None => return Err(invalid(format!("mapping at {:x} runs past the end of memory", start)))
+        };
+        let offset = r.uleb128();
+        let perms = get_string(&mut r, data)?;
+        let path = get_string(&mut r, data)?;
+        trace.maps.push(Mapping {
+            start,
+            end,
+            perms,
+            offset,
+            path: if path.is_empty() { None } else { Some(path) }
+        });
+    }
+
+    let mut code: HashMap<u64, &[u8]> = HashMap::new();
+    let mut last = 0u64;
+    for _ in 0..get_count(&mut r, data)? {
+        let rip = get_delta(&mut r, &mut last);
+        let bytes = get_bytes(&mut r, data)?;
+        if bytes.len() > 15 {
+            return Err(invalid(format!("instruction at {:x} is {} bytes long", rip, bytes.len())));
+        }
+        code.insert(rip, bytes);
+    }
+
+    let mut last = 0u64;
+    for _ in 0..get_count(&mut r, data)? {
+        let rip = get_delta(&mut r, &mut last);
+        let bytes = match code.get(&rip) {
+            Some(bytes) => *bytes,
+            None => return Err(invalid(format!("no instruction bytes for {:x}", rip)))
+        };
+        let mut step = Step {
+            rip,
+            code: [0u8; 15],
+            code_len: bytes.len(),
+            mem_accesses: Vec::new()
+        };
+        for (dst, src) in step.code.iter_mut().zip(bytes.iter()) {
+            *dst = *src;
+        }
+        trace.steps.push(step);
+    }
+
+    let mut last = 0u64;
+    for step in trace.steps.iter_mut() {
+        for _ in 0..get_count(&mut r, data)? {
+            let addr = get_delta(&mut r, &mut last);
+            let size = match u16::try_from(r.uleb128()) {
+                Ok(size) => size,
+                Err(_) => return Err(invalid(format!("access at {:x} is too wide", addr)))
+            };
+            let kind = match r.u8() {
+                0 => AccessKind::Load,
+                1 => AccessKind::Store,
+                2 => AccessKind::LoadStore,
+                k => return Err(invalid(format!("unknown access kind {}", k)))
+            };
+            step.mem_accesses.push(MemAccess { addr, size, kind });
+        }
+    }
+
+    if r.pos > data.len() {
+        return Err(invalid("truncated".to_string()));
+    }
+
+    Ok((Header { target, build_id }, trace))
+}
+
+/// Write `trace` to `path`.
+pub fn write(path: &Path, header: &Header, trace: &Trace) -> io::Result<()> {
+    fs::write(path, encode(header, trace))
+}
+
+/// Read a trace written by `write`.
+pub fn read(path: &Path) -> io::Result<(Header, Trace)> {
+    let data = fs::read(path)?;
+    decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(rip: u64, code: &[u8], mem_accesses: Vec<MemAccess>) -> Step {
+        let mut step = Step { rip, code: [0u8; 15], code_len: code.len(), mem_accesses };
+        step.code[..code.len()].copy_from_slice(code);
+        step
+    }
+
+    fn sample() -> (Header, Trace) {
+        let header = Header { target: "aes".to_string(), build_id: Some(vec![0xde, 0xad, 0xbe, 0xef]) };
+        let load = MemAccess { addr: 0x7ffc0010, size: 8, kind: AccessKind::Load };
+        let store = MemAccess { addr: 0x7ffc0008, size: 4, kind: AccessKind::Store };
+        let trace = Trace {
+            class: InputClass::AllOnes,
+            inputs: vec![vec![0xff; 16], vec![]],
+            input_addrs: vec![0x1000100, 0x1000200],
+            inputs_addr: 0x1000010,
+            steps: vec![
+                step(0x401000, &[0x48, 0x8b, 0x06], vec![load]),
+                step(0x401003, &[0x89, 0x07], vec![store]),
+                step(0x401000, &[0x48, 0x8b, 0x06], vec![load]),
+                step(0x400ff0, &[0xc3], vec![])
+            ],
+            maps: vec![
+                Mapping { start: 0x400000, end: 0x402000, perms: "r-xp".to_string(), offset: 0,
+                          path: Some("/bin/target".to_string()) },
+                Mapping { start: 0x7ffc0000, end: 0x7ffc1000, perms: "rw-p".to_string(), offset: 0,
+                          path: None }
+            ]
+        };
+        (header, trace)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let (header, trace) = sample();
+        let dir = std::env::temp_dir().join(format!("fixed-time-tester-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("aes-0.trace");
+        write(&path, &header, &trace).unwrap();
+        let (read_header, read_trace) = read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read_header.target, header.target);
+        assert_eq!(read_header.build_id, header.build_id);
+        assert_eq!(read_trace.class, trace.class);
+        assert_eq!(read_trace.inputs, trace.inputs);
+        assert_eq!(read_trace.input_addrs, trace.input_addrs);
+        assert_eq!(read_trace.inputs_addr, trace.inputs_addr);
+        assert_eq!(read_trace.steps.len(), trace.steps.len());
+        for (a, b) in read_trace.steps.iter().zip(trace.steps.iter()) {
+            assert_eq!((a.rip, a.code()), (b.rip, b.code()));
+            assert_eq!(a.mem_accesses, b.mem_accesses);
+        }
+        assert_eq!(read_trace.maps.len(), trace.maps.len());
+        for (a, b) in read_trace.maps.iter().zip(trace.maps.iter()) {
+            assert_eq!((a.start, a.end, &a.perms, a.offset, &a.path), (b.start, b.end, &b.perms, b.offset, &b.path));
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let (header, trace) = sample();
+        let data = encode(&header, &trace);
+        for len in 0..data.len() {
+            assert!(decode(&data[..len]).is_err(), "decoded the first {} of {} bytes", len, data.len());
+        }
+    }
+
+    #[test]
+    fn mapping_past_the_end_of_memory() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        put_bytes(&mut data, b"aes");
+        put_bytes(&mut data, b"random");
+        put_bytes(&mut data, &[]);
+        put_uleb128(&mut data, 0);
+        put_uleb128(&mut data, 0);
+        put_uleb128(&mut data, 1);
+        put_uleb128(&mut data, u64::MAX - 0x10);
+        put_uleb128(&mut data, 0x20);
+        put_uleb128(&mut data, 0);
+        put_bytes(&mut data, b"rw-p");
+        put_bytes(&mut data, &[]);
+        put_uleb128(&mut data, 0);
+        put_uleb128(&mut data, 0);
+        let error = decode(&data).err().unwrap();
+        assert!(error.to_string().contains("past the end of memory"), "{}", error);
+    }
+}
@@ -0,0 +1,1361 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::io::{PipeReader, PipeWriter, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::sys;
+use crate::decoder;
+use crate::decoder::{disassemble, Instruction, Memory, OperandKind, Register, Segment};
+use crate::access;
+use crate::access::{AccessKind, MemAccess, StringOp};
+use crate::report;
+use crate::report::Cluster;
+use crate::symbols::Symbolizer;
+use crate::maps;
+use crate::maps::Mapping;
+use crate::normalize::{AddressSpace, Normalization};
+use crate::latency::Findings;
+use crate::taint::{Leaks, TaintState};
+use crate::inputs;
+use crate::inputs::{InputBuffers, InputClass, InputGenerator};
+use crate::elf::{Elf, Reader};
+use crate::tracefile;
+use crate::golden;
+use crate::golden::Shape;
+use crate::breakpoint::{Breakpoint, FunctionMarkers};
+use crate::ptrace;
+use crate::ptrace::{Fork, StopEvent, Tracee};
+use crate::ConstantTimeTarget;
+use crate::Config;
+
+/// A single instruction executed by the target.
+pub struct Step {
+    pub rip: u64,
+    pub code: [u8; 15],
+    pub code_len: usize,
+    /// The memory accesses made by the instruction, in operand order.
+    pub mem_accesses: Vec<MemAccess>
+}
+
+impl Step {
+    /// The bytes of the instruction as they were in the tracee when it executed.
+    pub fn code(&self) -> &[u8] {
+        &self.code[..self.code_len]
+    }
+}
+
+/// Everything recorded about a single run of a target.
+pub struct Trace {
+    /// The class the inputs were drawn from.
+    pub class: InputClass,
+    pub inputs: Vec<Vec<u8>>,
+    /// Where each input buffer was in the tracee.
+    pub input_addrs: Vec<u64>,
+    /// Where the slice of input buffers that `run` was handed was in the tracee.
+    pub inputs_addr: u64,
+    pub steps: Vec<Step>,
+    /// The tracee's memory map at the end of the run. Only read when addresses are
+    /// being normalized.
+    pub maps: Vec<Mapping>
+}
+
+impl Trace {
+    fn new() -> Trace {
+        Trace {
+            class: InputClass::Random,
+            inputs: Vec::new(),
+            input_addrs: Vec::new(),
+            inputs_addr: 0,
+            steps: Vec::new(),
+            maps: Vec::new()
+        }
+    }
+
+    /// The layout of the tracee's memory during this run, for normalizing addresses.
+    pub fn address_space(&self) -> AddressSpace {
+        let inputs: Vec<(u64, u64)> = self.input_addrs.iter()
+            .zip(self.inputs.iter())
+            .map(|(&addr, input)| (addr, input.len() as u64))
+            .collect();
+        let list = (self.inputs_addr, (self.inputs.len() * mem::size_of::<Vec<u8>>()) as u64);
+        AddressSpace::new(&self.maps, &inputs, list)
+    }
+}
+
+fn signal_tracer_stop() {
+    let _ = ptrace::raise(sys::Signals::SIGSTOP);
+}
+
+fn signal_tracer_begin() {
+    let _ = ptrace::raise(sys::Signals::SIGUSR1);
+}
+
+fn write_u64(pipe: &mut PipeWriter, value: u64) {
+    if pipe.write_all(&value.to_le_bytes()).is_err() {
+        panic!("Couldn't write to tracer pipe");
+    }
+}
+
+fn read_u64(pipe: &mut PipeReader) -> u64 {
+    let mut bytes = [0u8; 8];
+    if pipe.read_exact(&mut bytes).is_err() {
+        panic!("Couldn't read from tracer pipe");
+    }
+    u64::from_le_bytes(bytes)
+}
+
+// The inputs are generated in the child, so they are sent up a pipe for the
+// divergence report. This happens just before the begin marker so that it isn't
+// traced; the inputs need to fit in the pipe buffer since the tracer only reads
+// them once the marker has been hit.
+fn send_inputs(pipe: &mut PipeWriter, class: InputClass, inputs: &[Vec<u8>]) {
+    let class_index = InputClass::all().iter().position(|c| *c == class).unwrap();
+    write_u64(pipe, class_index as u64);
+    write_u64(pipe, inputs.len() as u64);
+    write_u64(pipe, inputs.as_ptr() as u64);
+    for input in inputs.iter() {
+        write_u64(pipe, input.as_ptr() as u64);
+        write_u64(pipe, input.len() as u64);
+        if pipe.write_all(input).is_err() {
+            panic!("Couldn't write to tracer pipe");
+        }
+    }
+}
+
+fn recv_inputs(pipe: &mut PipeReader, trace: &mut Trace) {
+    trace.class = InputClass::all()[read_u64(pipe) as usize];
+    let count = read_u64(pipe) as usize;
+    trace.inputs_addr = read_u64(pipe);
+    trace.inputs = Vec::with_capacity(count);
+    trace.input_addrs = Vec::with_capacity(count);
+    for _ in 0..count {
+        let addr = read_u64(pipe);
+        let len = read_u64(pipe) as usize;
+        let mut input = vec![0u8; len];
+        if pipe.read_exact(&mut input).is_err() {
+            panic!("Couldn't read from tracer pipe");
+        }
+        trace.inputs.push(input);
+        trace.input_addrs.push(addr);
+    }
+}
+
+// The function whose calls are traced, if breakpoints are used rather than the
+// signals around `run`.
+fn traced_function(target: &dyn ConstantTimeTarget, config: &Config) -> Option<String> {
+    config.function.clone().or_else(|| target.traced_function().map(|f| f.to_string()))
+}
+
+// Everything the child does once it has been forked: opt in to tracing, stop so
+// that the tracer can catch up, and then run the target between markers.
+fn run_child(target: &mut dyn ConstantTimeTarget, config: &Config, inputs_pipe: &mut PipeWriter) {
+    if Tracee::trace_me().is_err() {
+        panic!("Failed to setup tracing");
+    }
+    target.setup();
+    // Stop once everything is set up, so the tracer can put breakpoints in without
+    // them being hit by `setup`.
+    signal_tracer_stop();
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let markers = traced_function(target, config).is_none();
+
+    let mut buffers = InputBuffers::new();
+    for &class in inputs::selected_classes(target, config).iter() {
+        let mut generator = InputGenerator::new(class);
+        for _ in 0..config.iterations {
+            let generated = generator.next(target, &mut rng);
+            let mut inputs = buffers.fill(&generated);
+            send_inputs(inputs_pipe, class, inputs);
+            black_box(&mut inputs);
+            if markers {
+                signal_tracer_begin();
+            }
+            target.run(inputs);
+            if markers {
+                signal_tracer_stop();
+            }
+        }
+    }
+}
+
+fn reg_value(regs: &libc::user_regs_struct, reg: Register) -> u64 {
+    let (num, size, high) = match reg {
+        Register::Gpr { num, size, high } => (num, size, high),
+        _ => panic!("I don't recognize the register type: {:?}", reg)
+    };
+    let value = match num {
+        0 => regs.rax,
+        1 => regs.rcx,
+        2 => regs.rdx,
+        3 => regs.rbx,
+        4 => regs.rsp,
+        5 => regs.rbp,
+        6 => regs.rsi,
+        7 => regs.rdi,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        15 => regs.r15,
+        _ => unreachable!()
+    };
+    let value = if high { value >> 8 } else { value };
+    match size {
+        8 => value,
+        size => value & ((1 << (8 * size as u64)) - 1)
+    }
+}
+
+// Decode the instruction a step executed. The bytes were just read from the
+// tracee, so anything the decoder can't make sense of is a bug.
+fn decode(rip: u64, code: &[u8]) -> Instruction {
+    match decoder::decode(rip, code) {
+        Some(instruction) => instruction,
+        None => panic!("Couldn't decode instruction at {:x}", rip)
+    }
+}
+
+/// Compute the effective address of a memory operand following the x86-64 rules:
+/// FS and GS overrides add the segment base, and a 32-bit address size (0x67
+/// prefix) truncates the result before the segment base is added. RIP-relative
+/// operands have already been resolved by the decoder.
+pub fn effective_address(regs: &libc::user_regs_struct, mem: &Memory) -> u64 {
+    let base = mem.base.map_or(0, |reg| reg_value(regs, reg));
+    let index = mem.index.map_or(0, |reg| reg_value(regs, reg));
+    let addr = base.wrapping_add(mem.disp).wrapping_add(index.wrapping_mul(mem.scale as u64));
+    let addr = if mem.addr32 { addr & 0xffffffff } else { addr };
+    let segment_base = match mem.segment {
+        Some(Segment::Fs) => regs.fs_base,
+        Some(Segment::Gs) => regs.gs_base,
+        None => 0
+    };
+    addr.wrapping_add(segment_base)
+}
+
+fn find_mem_access(regs: &libc::user_regs_struct, instruction: &Instruction, mem_access: &mut Vec<MemAccess>) {
+    for (i, op) in instruction.operands.iter().enumerate() {
+        let mem = match op.kind {
+            OperandKind::Memory(ref mem) => mem,
+            _ => continue
+        };
+        if let Some(kind) = access::classify(&instruction.mnemonic, i) {
+            mem_access.push(MemAccess {
+                addr: effective_address(regs, mem),
+                size: op.size,
+                kind
+            });
+        }
+    }
+}
+
+// How many bytes a PUSH or POP moves: 8, or 2 with an operand size prefix. PUSHF
+// and POPF are the 16-bit forms of PUSHFQ and POPFQ, and segment registers are
+// pushed as 8 bytes despite their size. CALL and RET always move the whole return
+// address.
+fn stack_operand_size(instruction: &Instruction) -> u16 {
+    let operand = instruction.operands.first().map(|op| (op.kind, op.size));
+    match (instruction.mnemonic.as_str(), operand) {
+        ("PUSHF", _) | ("POPF", _) => 2,
+        ("PUSH", Some((kind, 2))) | ("POP", Some((kind, 2))) if kind != OperandKind::Register(Register::Other) => 2,
+        _ => 8
+    }
+}
+
+// The stack and string instructions access memory through RSP, RSI and RDI
+// without a memory operand, so their accesses have to be worked out by hand.
+// Returns true if the instruction was one of these, in which case any explicit
+// operands it has have already been accounted for.
+fn find_implicit_mem_access(regs: &libc::user_regs_struct, instruction: &Instruction,
+                            mem_access: &mut Vec<MemAccess>) -> bool {
+    let push = |mem_access: &mut Vec<MemAccess>, addr: u64, size: u16, kind: AccessKind| {
+        mem_access.push(MemAccess { addr, size, kind });
+    };
+
+    if let Some(string) = access::string_instruction(instruction) {
+        // With a REP prefix each iteration is a separate single-step, and one
+        // with RCX already zero doesn't touch memory at all.
+        if string.rep && regs.rcx == 0 {
+            return true;
+        }
+        match string.op {
+            StringOp::Movs => {
+                push(mem_access, string.source(regs), string.size, AccessKind::Load);
+                push(mem_access, regs.rdi, string.size, AccessKind::Store);
+            }
+            StringOp::Stos => push(mem_access, regs.rdi, string.size, AccessKind::Store),
+            StringOp::Lods => push(mem_access, string.source(regs), string.size, AccessKind::Load),
+            StringOp::Cmps => {
+                push(mem_access, string.source(regs), string.size, AccessKind::Load);
+                push(mem_access, regs.rdi, string.size, AccessKind::Load);
+            }
+            StringOp::Scas => push(mem_access, regs.rdi, string.size, AccessKind::Load)
+        }
+        return true;
+    }
+
+    match instruction.mnemonic.as_str() {
+        "PUSH" | "PUSHF" | "PUSHFQ" | "CALL" => {
+            // The explicit operand, if it is in memory, is read before the push.
+            let size = stack_operand_size(instruction);
+            find_mem_access(regs, instruction, mem_access);
+            push(mem_access, regs.rsp.wrapping_sub(size as u64), size, AccessKind::Store);
+        }
+        "POP" | "POPF" | "POPFQ" | "RET" => {
+            let size = stack_operand_size(instruction);
+            push(mem_access, regs.rsp, size, AccessKind::Load);
+            // A memory destination is addressed with RSP already incremented.
+            let mut popped = *regs;
+            popped.rsp = regs.rsp.wrapping_add(size as u64);
+            find_mem_access(&popped, instruction, mem_access);
+        }
+        "LEAVE" => push(mem_access, regs.rbp, 8, AccessKind::Load),
+        "ENTER" => {
+            // ENTER imm16, imm8: the second immediate is the nesting level.
+            let level = match instruction.operands.get(1).map(|op| op.kind) {
+                Some(OperandKind::Immediate(level)) => level & 0x1f,
+                _ => 0
+            };
+            let frame = regs.rsp.wrapping_sub(8);
+            push(mem_access, frame, 8, AccessKind::Store);
+            if level > 0 {
+                for i in 1..level {
+                    push(mem_access, regs.rbp.wrapping_sub(8 * i), 8, AccessKind::Load);
+                    push(mem_access, frame.wrapping_sub(8 * i), 8, AccessKind::Store);
+                }
+                push(mem_access, frame.wrapping_sub(8 * level), 8, AccessKind::Store);
+            }
+        }
+        _ => return false
+    }
+    true
+}
+
+// A tracee stopped before the runs that are to be traced, and how to find them.
+struct Session {
+    pid: libc::pid_t,
+    // Used to name saved and golden traces.
+    name: String,
+    secret_inputs: Vec<usize>,
+    // Where the child sends the inputs of each run. None when attached to a
+    // process, whose inputs aren't known.
+    inputs_pipe: Option<PipeReader>,
+    // None if runs are delimited by signals from the child.
+    markers: Option<FunctionMarkers>,
+    total_runs: usize,
+    // Loaded the first time something needs to be reported, while the tracee is
+    // still around.
+    symbols: Option<Symbolizer>,
+    // Every thread besides the main one that is known to be alive.
+    threads: HashSet<Tracee>,
+    // The thread that stopped most recently. `trace_runs` leaves it stopped.
+    last_stop: Tracee,
+    // Whether the tracee was running before it was attached to, in which case it's
+    // detached from rather than killed if tracing fails.
+    attached: bool,
+    exited: bool,
+    detached: bool
+}
+
+impl Session {
+    fn new(pid: libc::pid_t, name: String, total_runs: usize) -> Session {
+        Session {
+            pid,
+            name,
+            secret_inputs: Vec::new(),
+            inputs_pipe: None,
+            markers: None,
+            total_runs,
+            symbols: None,
+            threads: HashSet::new(),
+            last_stop: Tracee::new(pid),
+            attached: false,
+            exited: false,
+            detached: false
+        }
+    }
+}
+
+// If tracing is abandoned part way (a ptrace call failed or the tracer panicked),
+// don't leave the tracee stopped with breakpoints in it. A process that was
+// attached to is let go of as well as it can be; one that was started is killed.
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.exited || self.detached {
+            return;
+        }
+        let main = Tracee::new(self.pid);
+        if self.attached {
+            if let Some(markers) = self.markers.take() {
+                let _ = markers.remove(self.last_stop);
+            }
+            for tracee in self.threads.iter().chain(Some(main).iter()) {
+                let _ = tracee.detach();
+            }
+        } else {
+            let _ = main.kill();
+            let _ = main.wait_event();
+        }
+    }
+}
+
+// What a stop of the tracee means for the trace.
+#[allow(clippy::large_enum_variant)]
+enum Event {
+    // With the registers at the function's entry when a breakpoint marks the start
+    // of the run, since its first instruction is part of it.
+    Begin(Option<libc::user_regs_struct>),
+    Step(libc::user_regs_struct),
+    End
+}
+
+struct Analysis {
+    latency: Findings,
+    // Reset at the start of every run.
+    taint: Option<TaintState>,
+    leaks: Leaks
+}
+
+impl Analysis {
+    fn new() -> Analysis {
+        Analysis {
+            latency: Findings::new(),
+            taint: None,
+            leaks: Leaks::new()
+        }
+    }
+}
+
+fn record_step(tracee: Tracee, regs: &libc::user_regs_struct, config: &Config,
+               analysis: &mut Analysis) -> ptrace::Result<Step> {
+    let mut step = Step {
+        rip: regs.rip,
+        code: [0u8; 15],
+        code_len: 0,
+        mem_accesses: Vec::new()
+    };
+    step.code_len = tracee.read_mem(regs.rip, &mut step.code)?;
+    if !config.checks.memory() && config.latency.is_none() && analysis.taint.is_none() {
+        return Ok(step);
+    }
+
+    let instruction = decode(regs.rip, step.code());
+    if config.checks.memory() && !find_implicit_mem_access(regs, &instruction, &mut step.mem_accesses) {
+        find_mem_access(regs, &instruction, &mut step.mem_accesses);
+    }
+    if let Some(ref policy) = config.latency {
+        if policy.is_variable(&instruction.mnemonic) {
+            analysis.latency.record(regs.rip, &instruction.mnemonic);
+        }
+    }
+    if let Some(ref mut taint) = analysis.taint {
+        let leaks = taint.step(regs, &instruction, config.latency.as_ref());
+        for &leak in leaks.iter() {
+            analysis.leaks.record(regs.rip, leak, &instruction.mnemonic);
+        }
+    }
+    Ok(step)
+}
+
+/// Fork, run `target` `config.iterations` times for each of its input classes in
+/// the child and single-step each run, comparing the instructions executed and/or
+/// the memory addresses accessed against the first run (or the saved trace in
+/// `config.reference`). Returns `true` if every run behaved identically; if not, a
+/// report of where each distinct trace diverged from the reference is printed,
+/// followed by a summary of the distinct traces.
+///
+/// Runs are delimited by signals the child raises around `run`, or, if the target
+/// has a `traced_function` (or `config.function` is set), by breakpoints on the
+/// entry to that function and its return address. Threads the target starts
+/// during a run are traced separately and compared with those the first run
+/// started.
+///
+/// If `config.golden_dir` is set, each run must also have the trace shape recorded
+/// for the target there by an earlier invocation, so changes to the target that
+/// affect every run alike are caught too.
+///
+/// If `config.latency` is set, any instruction it lists that is executed also
+/// counts as a failure. If `config.taint` is set, the target's secret inputs are
+/// tracked through each run and any branch, address or repeat count derived from
+/// them is a failure.
+pub fn trace_target(target: &mut dyn ConstantTimeTarget, config: &Config) -> bool {
+    let (reader, mut writer) = match io::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => panic!("Couldn't create pipe: {}", e)
+    };
+
+    let child_pid = match ptrace::fork() {
+        Ok(Fork::Parent(pid)) => pid,
+        Ok(Fork::Child) => {
+            drop(reader);
+            run_child(target, config, &mut writer);
+            process::exit(0);
+        }
+        Err(e) => panic!("Couldn't start {}: {}", target.name(), e)
+    };
+    drop(writer);
+
+    let total_runs = inputs::selected_classes(target, config).len() * config.iterations;
+    let mut session = Session::new(child_pid, target.name().to_string(), total_runs);
+    session.secret_inputs = target.secret_inputs();
+    session.inputs_pipe = Some(reader);
+    match trace_child(target, config, &mut session) {
+        Ok(same) => same,
+        Err(e) => {
+            println!("Tracing {} failed: {}", target.name(), e);
+            false
+        }
+    }
+}
+
+// The part of `trace_target` that runs once the child has been forked.
+fn trace_child(target: &dyn ConstantTimeTarget, config: &Config, session: &mut Session) -> ptrace::Result<bool> {
+    let child = Tracee::new(session.pid);
+    match child.wait_event()? {
+        StopEvent::Signal(sys::Signals::SIGSTOP) => (),
+        StopEvent::Signal(sig) if sys::Signals::is_crash(sig) => {
+            println!("{} crashed in setup", target.name());
+            report_crash(child, sig, &Symbolizer::new(session.pid));
+            return Ok(false);
+        }
+        event => {
+            println!("{} didn't start: {:?}", target.name(), event);
+            return Ok(false);
+        }
+    }
+
+    if let Some(name) = traced_function(target, config) {
+        let symbols = Symbolizer::new(session.pid);
+        let entry = match symbols.address_of(&name) {
+            Some(entry) => entry,
+            None => {
+                println!("Couldn't find {} in {}", name, target.name());
+                return Ok(false);
+            }
+        };
+        println!("Tracing calls to {} at {:x}", name, entry);
+        session.markers = Some(FunctionMarkers::new(child, entry)?);
+        session.symbols = Some(symbols);
+    }
+
+    let same = trace_runs(session, config)?;
+    if !session.exited {
+        run_to_exit(session)?;
+    }
+    Ok(same)
+}
+
+/// Attach to the running process `pid`, trace `config.iterations` calls to
+/// `function` in it, and detach, leaving the process as it was. The inputs of each
+/// call aren't known, so reports show only where the calls differed.
+///
+/// Every thread of the process is attached to, since one that wasn't would be
+/// killed by the breakpoint on `function`, and threads it starts afterwards are
+/// followed. Only the thread making each call is single-stepped.
+pub fn trace_process(pid: libc::pid_t, function: &str, config: &Config) -> bool {
+    let tracee = match Tracee::attach(pid) {
+        Ok(tracee) => tracee,
+        Err(e) => {
+            println!("Couldn't attach to process {}: {}", pid, e);
+            return false;
+        }
+    };
+    let mut session = Session::new(pid, function.to_string(), config.iterations);
+    session.attached = true;
+    match trace_attached(tracee, function, config, &mut session) {
+        Ok(same) => same,
+        Err(e) => {
+            println!("Tracing process {} failed: {}", pid, e);
+            false
+        }
+    }
+}
+
+// The part of `trace_process` that runs once the process has been attached to.
+fn trace_attached(tracee: Tracee, function: &str, config: &Config,
+                  session: &mut Session) -> ptrace::Result<bool> {
+    match tracee.wait_event()? {
+        StopEvent::Signal(sys::Signals::SIGSTOP) => (),
+        event => {
+            println!("Process {} didn't stop when attached to: {:?}", tracee.tid, event);
+            return Ok(false);
+        }
+    }
+    attach_threads(session)?;
+
+    let symbols = Symbolizer::new(tracee.tid);
+    let entry = match symbols.address_of(function) {
+        Some(entry) => entry,
+        None => {
+            println!("Couldn't find {} in process {}", function, tracee.tid);
+            return Ok(false);
+        }
+    };
+    println!("Tracing {} calls to {} at {:x} in process {}", config.iterations, function, entry, tracee.tid);
+    session.markers = Some(FunctionMarkers::new(tracee, entry)?);
+    session.symbols = Some(symbols);
+
+    let same = trace_runs(session, config)?;
+    if !session.exited {
+        detach(session)?;
+    }
+    Ok(same)
+}
+
+// Attach to the threads of an attached process besides the main one. They keep
+// running until they are, and may start more threads in the meantime, so the list
+// is read again until nothing new turns up.
+fn attach_threads(session: &mut Session) -> ptrace::Result<()> {
+    loop {
+        let tids: Vec<libc::pid_t> = match fs::read_dir(format!("/proc/{}/task", session.pid)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                .collect(),
+            Err(e) => panic!("Couldn't list the threads of process {}: {}", session.pid, e)
+        };
+        let mut attached = false;
+        for tid in tids {
+            let tracee = Tracee::new(tid);
+            if tid == session.pid || session.threads.contains(&tracee) {
+                continue;
+            }
+            match Tracee::attach(tid) {
+                Ok(_) => (),
+                // It exited since the list was read.
+                Err(ref e) if e.errno == libc::ESRCH => continue,
+                Err(e) => return Err(e)
+            }
+            loop {
+                match tracee.wait_event()? {
+                    StopEvent::Signal(sys::Signals::SIGSTOP) => {
+                        session.threads.insert(tracee);
+                        attached = true;
+                        break;
+                    }
+                    StopEvent::Exited(_) | StopEvent::Killed(_) => break,
+                    // A signal that was already on its way; deliver it and wait for
+                    // the stop.
+                    StopEvent::Signal(sig) => tracee.cont(sig)?,
+                    StopEvent::Clone(_) => unreachable!()
+                }
+            }
+        }
+        if !attached {
+            return Ok(());
+        }
+    }
+}
+
+// The address the kernel will start the freshly exec'd process `pid` at, from its
+// auxiliary vector. The dynamic linker has loaded every library the program
+// depends on by the time it gets there.
+fn entry_point(pid: libc::pid_t) -> u64 {
+    const AT_ENTRY: u64 = 9;
+    let auxv = match fs::read(format!("/proc/{}/auxv", pid)) {
+        Ok(auxv) => auxv,
+        Err(e) => panic!("Couldn't read auxiliary vector of {}: {}", pid, e)
+    };
+    let mut r = Reader::new(&auxv);
+    while !r.is_empty() {
+        let (key, value) = (r.u64(), r.u64());
+        if key == AT_ENTRY {
+            return value;
+        }
+    }
+    panic!("No entry point in auxiliary vector of {}", pid);
+}
+
+/// Start `program` with `args` and `env` (in addition to this process's own
+/// environment), trace the first `config.iterations` calls it makes to
+/// `function`, and then let it run to completion untraced. As with
+/// `trace_process`, the inputs of each call aren't known.
+pub fn trace_program(program: &Path, args: &[String], env: &[(String, String)], function: &str,
+                     config: &Config) -> bool {
+    let mut environment: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| !env.iter().any(|(n, _)| n == name))
+        .collect();
+    environment.extend_from_slice(env);
+
+    // Build everything execve needs before forking.
+    let program_c = CString::new(program.as_os_str().as_bytes()).unwrap();
+    let mut args_c = vec![program_c.clone()];
+    args_c.extend(args.iter().map(|a| CString::new(a.as_str()).unwrap()));
+    let env_c: Vec<CString> = environment.iter().map(|(n, v)| CString::new(format!("{}={}", n, v)).unwrap()).collect();
+
+    let tracee = match ptrace::spawn(&program_c, &args_c, &env_c) {
+        Ok(tracee) => tracee,
+        Err(e) => {
+            println!("Couldn't run {}: {}", program.display(), e);
+            return false;
+        }
+    };
+
+    let mut session = Session::new(tracee.tid, function.to_string(), config.iterations);
+    match trace_exec(program, function, config, &mut session) {
+        Ok(same) => same,
+        Err(e) => {
+            println!("Tracing {} failed: {}", program.display(), e);
+            false
+        }
+    }
+}
+
+// The part of `trace_program` that runs once the program has been forked.
+fn trace_exec(program: &Path, function: &str, config: &Config, session: &mut Session) -> ptrace::Result<bool> {
+    let tracee = Tracee::new(session.pid);
+    // The child stops with SIGTRAP once the exec has happened.
+    match tracee.wait_event()? {
+        StopEvent::Signal(sys::Signals::SIGTRAP) => (),
+        _ => {
+            println!("Couldn't run {}", program.display());
+            return Ok(false);
+        }
+    }
+
+    // Libraries aren't loaded yet, so run to the program's entry point before
+    // looking for the function.
+    let entry = Breakpoint::insert(tracee, entry_point(tracee.tid))?;
+    tracee.cont(0)?;
+    match tracee.wait_event()? {
+        StopEvent::Signal(sys::Signals::SIGTRAP) => (),
+        event => {
+            println!("{} didn't reach its entry point: {:?}", program.display(), event);
+            return Ok(false);
+        }
+    }
+    let mut regs = tracee.regs()?;
+    regs.rip = entry.addr;
+    tracee.set_regs(&regs)?;
+    entry.remove(tracee)?;
+
+    let symbols = Symbolizer::new(tracee.tid);
+    let function_addr = match symbols.address_of(function) {
+        Some(addr) => addr,
+        None => {
+            println!("Couldn't find {} in {}", function, program.display());
+            return Ok(false);
+        }
+    };
+    println!("Tracing {} calls to {} at {:x} in {}", config.iterations, function, function_addr,
+             program.display());
+    session.markers = Some(FunctionMarkers::new(tracee, function_addr)?);
+    session.symbols = Some(symbols);
+
+    let same = trace_runs(session, config)?;
+    if !session.exited {
+        detach(session)?;
+        tracee.wait_event()?;
+    }
+    Ok(same)
+}
+
+// Let a stopped thread carry on, single-stepping it if it's being traced, and
+// deliver `sig` to it unless it's 0.
+fn forward(tracee: Tracee, step: bool, sig: libc::c_int) -> ptrace::Result<()> {
+    if step {
+        tracee.step(sig)
+    } else {
+        tracee.cont(sig)
+    }
+}
+
+fn resume(tracee: Tracee, step: bool) -> ptrace::Result<()> {
+    forward(tracee, step, 0)
+}
+
+// Let the tracee run to completion untraced.
+fn run_to_exit(session: &mut Session) -> ptrace::Result<()> {
+    resume(session.last_stop, false)?;
+    loop {
+        let (tracee, event) = ptrace::wait_any()?;
+        match event {
+            StopEvent::Exited(_) | StopEvent::Killed(_) if tracee.tid == session.pid => break,
+            StopEvent::Exited(_) | StopEvent::Killed(_) => (),
+            // Thread creation shows up as clone events and SIGSTOP stops;
+            // anything else is meant for the tracee.
+            StopEvent::Clone(_) |
+            StopEvent::Signal(sys::Signals::SIGTRAP) |
+            StopEvent::Signal(sys::Signals::SIGSTOP) => resume(tracee, false)?,
+            StopEvent::Signal(sig) => forward(tracee, false, sig)?
+        }
+    }
+    session.exited = true;
+    Ok(())
+}
+
+// Print where a thread was and what it was executing when it received a signal
+// that means it crashed.
+fn report_crash(tracee: Tracee, sig: libc::c_int, symbols: &Symbolizer) {
+    let regs = match tracee.regs() {
+        Ok(regs) => regs,
+        Err(_) => {
+            println!("Thread {} received {}", tracee.tid, sys::Signals::name(sig));
+            return;
+        }
+    };
+    let mut code = [0u8; 15];
+    let len = tracee.read_mem(regs.rip, &mut code).unwrap_or(0);
+    println!("Thread {} received {} at {:x} in {}", tracee.tid, sys::Signals::name(sig), regs.rip,
+             symbols.describe(regs.rip));
+    if len > 0 {
+        println!("    {:016x}  {}", regs.rip, disassemble(regs.rip, &code[..len]));
+    }
+    let values = [
+        ("rax", regs.rax), ("rbx", regs.rbx), ("rcx", regs.rcx), ("rdx", regs.rdx),
+        ("rsi", regs.rsi), ("rdi", regs.rdi), ("rbp", regs.rbp), ("rsp", regs.rsp),
+        ("r8", regs.r8), ("r9", regs.r9), ("r10", regs.r10), ("r11", regs.r11),
+        ("r12", regs.r12), ("r13", regs.r13), ("r14", regs.r14), ("r15", regs.r15),
+        ("rip", regs.rip), ("eflags", regs.eflags), ("fs_base", regs.fs_base), ("gs_base", regs.gs_base)
+    ];
+    for row in values.chunks(4) {
+        let fields: Vec<String> = row.iter().map(|&(name, value)| format!("{:>7} {:016x}", name, value)).collect();
+        println!("   {}", fields.join(" "));
+    }
+}
+
+// Stop a thread that may be running. Returns false if it exited instead.
+fn stop_thread(tracee: Tracee) -> ptrace::Result<bool> {
+    if tracee.signal(sys::Signals::SIGSTOP).is_err() {
+        return Ok(false);
+    }
+    loop {
+        match tracee.wait_event() {
+            Ok(StopEvent::Signal(sys::Signals::SIGSTOP)) => return Ok(true),
+            // Some other stop was already pending; let it go and wait for ours.
+            Ok(StopEvent::Signal(sys::Signals::SIGTRAP)) | Ok(StopEvent::Clone(_)) => resume(tracee, false)?,
+            Ok(StopEvent::Signal(sig)) => forward(tracee, false, sig)?,
+            Ok(StopEvent::Exited(_)) | Ok(StopEvent::Killed(_)) | Err(_) => return Ok(false)
+        }
+    }
+}
+
+// Take the breakpoints out of the tracee and let all of its threads go.
+fn detach(session: &mut Session) -> ptrace::Result<()> {
+    let stopped = session.last_stop;
+    let mut threads: Vec<Tracee> = session.threads.iter().copied().collect();
+    threads.push(Tracee::new(session.pid));
+    let mut stopped_threads = Vec::new();
+    for &tracee in threads.iter() {
+        if tracee == stopped || stop_thread(tracee)? {
+            stopped_threads.push(tracee);
+        }
+    }
+    session.markers.take().unwrap().remove(stopped)?;
+    for tracee in stopped_threads.iter() {
+        tracee.detach()?;
+    }
+    session.detached = true;
+    Ok(())
+}
+
+// Trace `session.total_runs` runs of a tracee that is stopped before the first
+// of them. Returns with the thread that made the last run stopped, in
+// `session.last_stop`, unless the tracee exited.
+//
+// Every thread of the tracee is followed. A run belongs to the thread that hit the
+// marker starting it, and only the markers of that thread can end it. Threads it
+// starts during the run are single-stepped too, each into its own trace, and are
+// compared with the threads started (in the same order) by the first run.
+fn trace_runs(session: &mut Session, config: &Config) -> ptrace::Result<bool> {
+    let mut analysis = Analysis::new();
+    let result = step_runs(session, config, &mut analysis);
+    // Variable latency instructions and leaks are reported however the runs
+    // ended, since a tracee that crashed or a ptrace call that failed part way
+    // doesn't make what was already found any less true.
+    if analysis.latency.is_empty() && analysis.leaks.is_empty() {
+        return result;
+    }
+    let symbols = session.symbols.as_ref().expect("symbols are loaded when a run begins");
+    if !analysis.latency.is_empty() {
+        analysis.latency.print(symbols);
+    }
+    if !analysis.leaks.is_empty() {
+        analysis.leaks.print(symbols);
+    }
+    result.map(|_| false)
+}
+
+fn step_runs(session: &mut Session, config: &Config, analysis: &mut Analysis) -> ptrace::Result<bool> {
+    let pid = session.pid;
+    let main = Tracee::new(pid);
+    // A tracee that was started for tracing is killed if the tracer dies.
+    let options = if session.attached {
+        libc::PTRACE_O_TRACECLONE
+    } else {
+        libc::PTRACE_O_TRACECLONE | libc::PTRACE_O_EXITKILL
+    };
+    for &tracee in session.threads.iter().chain(Some(main).iter()) {
+        tracee.set_options(options)?;
+        resume(tracee, false)?;
+    }
+
+    let mut run = 0;
+    let mut reference: Option<Trace> = None;
+    let mut clusters: Vec<Cluster> = Vec::new();
+    if let Some(ref path) = config.reference {
+        match tracefile::read(path) {
+            Ok((header, trace)) => {
+                if header.target != session.name {
+                    println!("{} is a trace of {}, not {}", path.display(), header.target, session.name);
+                }
+                clusters.push(Cluster { hash: report::trace_hash(&trace, config), runs: Vec::new() });
+                reference = Some(trace);
+            }
+            Err(e) => panic!("Couldn't read reference trace {}: {}", path.display(), e)
+        }
+    }
+    // With --golden, every run must have the recorded shape. If there's nothing
+    // recorded yet (or it's being updated) the first run's shape is recorded.
+    let golden_path = config.golden_dir.as_ref().map(|dir| dir.join(format!("{}.golden", session.name)));
+    let mut golden_shape: Option<Shape> = match golden_path {
+        Some(ref path) if !config.update_golden => match golden::read(path) {
+            Ok(shape) => shape,
+            Err(e) => panic!("Couldn't read golden trace shape {}: {}", path.display(), e)
+        },
+        _ => None
+    };
+    let header = tracefile::Header {
+        target: session.name.clone(),
+        build_id: match config.save_dir {
+            Some(_) => Elf::open(Path::new(&format!("/proc/{}/exe", pid))).and_then(|elf| elf.build_id()),
+            None => None
+        }
+    };
+    let mut trace = Trace::new();
+    // The thread the current run belongs to.
+    let mut run_thread: Option<Tracee> = None;
+    // Threads started during the current run, with their traces so far.
+    let mut helpers: Vec<(Tracee, Trace)> = Vec::new();
+    let mut reference_helpers: Option<Vec<Trace>> = None;
+    // A new thread's first stop and its parent's clone event can arrive in either
+    // order. These hold whichever came first: threads that have stopped, and
+    // threads that are expected to, with whether they should be traced.
+    let mut new_threads: HashSet<Tracee> = HashSet::new();
+    let mut expected_threads: HashMap<Tracee, bool> = HashMap::new();
+    let mut same = true;
+    if config.taint && session.secret_inputs.is_empty() {
+        println!("{} has no secret inputs, so taint tracking won't find anything", session.name);
+    }
+    loop {
+        let (tracee, event) = ptrace::wait_any()?;
+        match event {
+            StopEvent::Exited(_) | StopEvent::Killed(_) if tracee != main => {
+                session.threads.remove(&tracee);
+                continue;
+            }
+            StopEvent::Exited(_) => {
+                println!("Tracee exited");
+                session.exited = true;
+                break;
+            }
+            StopEvent::Killed(sig) => {
+                println!("Tracee was killed by {}", sys::Signals::name(sig));
+                same = false;
+                session.exited = true;
+                break;
+            }
+            _ => ()
+        }
+        session.last_stop = tracee;
+        let helper = helpers.iter().position(|&(t, _)| t == tracee);
+        let stepping = run_thread == Some(tracee) || helper.is_some();
+
+        let stopsig = match event {
+            StopEvent::Clone(new_tid) => {
+                let new_thread = Tracee::new(new_tid);
+                session.threads.insert(new_thread);
+                if stepping {
+                    helpers.push((new_thread, Trace::new()));
+                }
+                if new_threads.remove(&new_thread) {
+                    resume(new_thread, stepping)?;
+                } else {
+                    expected_threads.insert(new_thread, stepping);
+                }
+                resume(tracee, stepping)?;
+                continue;
+            }
+            StopEvent::Signal(sig) => sig,
+            _ => unreachable!()
+        };
+        if stopsig == sys::Signals::SIGSTOP {
+            if let Some(step) = expected_threads.remove(&tracee) {
+                resume(tracee, step)?;
+                continue;
+            }
+            if tracee != main && !session.threads.contains(&tracee) {
+                new_threads.insert(tracee);
+                continue;
+            }
+        }
+
+        if sys::Signals::is_crash(stopsig) {
+            if session.symbols.is_none() {
+                session.symbols = Some(Symbolizer::new(pid));
+            }
+            report_crash(tracee, stopsig, session.symbols.as_ref().unwrap());
+            same = false;
+        }
+
+        if let Some(i) = helper {
+            if stopsig != sys::Signals::SIGTRAP {
+                forward(tracee, true, stopsig)?;
+                continue;
+            }
+            let user_regs = tracee.regs()?;
+            // Taint is only tracked in the thread the run belongs to.
+            let taint = analysis.taint.take();
+            let step = record_step(tracee, &user_regs, config, analysis)?;
+            analysis.taint = taint;
+            helpers[i].1.steps.push(step);
+            resume(tracee, true)?;
+            continue;
+        }
+
+        let event = match stopsig {
+            sys::Signals::SIGUSR1 if session.markers.is_none() && run_thread.is_none() => Event::Begin(None),
+            sys::Signals::SIGSTOP if session.markers.is_none() && run_thread == Some(tracee) => Event::End,
+            sys::Signals::SIGTRAP => {
+                let mut user_regs = tracee.regs()?;
+                match session.markers {
+                    Some(ref mut markers) => {
+                        if !markers.in_call() {
+                            if !markers.enter(tracee, &mut user_regs)? {
+                                // Left over from a thread that was traced by the
+                                // previous run, or from a breakpoint that has
+                                // since been taken out.
+                                markers.step_over(tracee, &mut user_regs)?;
+                                resume(tracee, false)?;
+                                continue;
+                            }
+                            Event::Begin(Some(user_regs))
+                        } else if run_thread != Some(tracee) {
+                            markers.step_over(tracee, &mut user_regs)?;
+                            resume(tracee, false)?;
+                            continue;
+                        } else if markers.returned(tracee, &user_regs)? {
+                            Event::End
+                        } else {
+                            // Before the step is recorded, so that the instruction
+                            // is read rather than the breakpoint.
+                            markers.lift_return(tracee, &user_regs)?;
+                            Event::Step(user_regs)
+                        }
+                    }
+                    None if run_thread == Some(tracee) => Event::Step(user_regs),
+                    None => {
+                        resume(tracee, false)?;
+                        continue;
+                    }
+                }
+            }
+            // Anything else is meant for the tracee.
+            sig => {
+                forward(tracee, stepping, sig)?;
+                continue;
+            }
+        };
+        match event {
+            Event::Begin(entry_regs) => {
+                run_thread = Some(tracee);
+                // Anything found during the runs is reported at the end, by which
+                // time the tracee may have gone, so symbols are loaded up front.
+                if (config.latency.is_some() || config.taint) && session.symbols.is_none() {
+                    session.symbols = Some(Symbolizer::new(pid));
+                }
+                if let Some(pipe) = session.inputs_pipe.as_mut() {
+                    recv_inputs(pipe, &mut trace);
+                }
+                if config.taint {
+                    let mut taint = TaintState::new();
+                    for &i in session.secret_inputs.iter() {
+                        taint.taint_memory(trace.input_addrs[i], trace.inputs[i].len() as u64);
+                    }
+                    analysis.taint = Some(taint);
+                }
+                if let Some(ref user_regs) = entry_regs {
+                    trace.steps.push(record_step(tracee, user_regs, config, analysis)?);
+                }
+                resume(tracee, true)?;
+            }
+            Event::Step(user_regs) => {
+                trace.steps.push(record_step(tracee, &user_regs, config, analysis)?);
+                resume(tracee, true)?;
+            }
+            Event::End => {
+                println!("Run {} completed. Total instructions: {}", run, trace.steps.len());
+                if config.normalization != Normalization::Absolute || config.save_dir.is_some() ||
+                   golden_path.is_some() {
+                    trace.maps = maps::read_maps(pid);
+                }
+                if let Some(ref dir) = config.save_dir {
+                    let path = dir.join(format!("{}-{}.trace", session.name, run));
+                    if let Err(e) = tracefile::write(&path, &header, &trace) {
+                        println!("Couldn't save run {} to {}: {}", run, path.display(), e);
+                    }
+                }
+
+                if let Some(ref path) = golden_path {
+                    let shape = Shape::of(&trace);
+                    match golden_shape {
+                        Some(ref golden) => {
+                            let differences = golden.differences(&shape);
+                            if !differences.is_empty() {
+                                println!("Run {} doesn't match the golden trace shape in {}: {}",
+                                         run, path.display(), differences.join(", "));
+                                same = false;
+                            }
+                        }
+                        None => {
+                            if let Err(e) = golden::write(path, &shape) {
+                                panic!("Couldn't write golden trace shape {}: {}", path.display(), e);
+                            }
+                            println!("Recorded golden trace shape in {}", path.display());
+                        }
+                    }
+                    if golden_shape.is_none() {
+                        golden_shape = Some(shape);
+                    }
+                }
+
+                // Every run is compared against the first. Runs that differ are
+                // grouped by hash, and the first run of each new group gets a full
+                // divergence report.
+                let hash = report::trace_hash(&trace, config);
+                let divergence = match reference {
+                    Some(ref reference) => report::compare(reference, &trace, config),
+                    None => None
+                };
+                match divergence {
+                    None => {
+                        if clusters.is_empty() {
+                            clusters.push(Cluster { hash, runs: Vec::new() });
+                        } else {
+                            println!("Run {} matches the reference", run);
+                        }
+                        clusters[0].runs.push(run);
+                    }
+                    Some(divergence) => {
+                        same = false;
+                        let index = clusters.iter().skip(1).position(|c| c.hash == hash);
+                        match index {
+                            Some(i) => {
+                                println!("Run {} diverged the same way as run {}", run, clusters[i + 1].runs[0]);
+                                clusters[i + 1].runs.push(run);
+                            }
+                            None => {
+                                if session.symbols.is_none() {
+                                    session.symbols = Some(Symbolizer::new(pid));
+                                }
+                                let reference_label = match config.reference {
+                                    Some(ref path) => format!("reference {}", path.display()),
+                                    None => "run 0".to_string()
+                                };
+                                report::print_divergence(reference.as_ref().unwrap(), &reference_label, &trace,
+                                                         &format!("run {}", run), &divergence,
+                                                         session.symbols.as_ref().unwrap());
+                                clusters.push(Cluster { hash, runs: vec![run] });
+                            }
+                        }
+                    }
+                }
+
+                // Threads started during the run are compared with those started
+                // by the first run.
+                let threads: Vec<Trace> = mem::take(&mut helpers).into_iter()
+                    .map(|(_, mut thread)| {
+                        thread.class = trace.class;
+                        thread.inputs = trace.inputs.clone();
+                        thread.input_addrs = trace.input_addrs.clone();
+                        thread.inputs_addr = trace.inputs_addr;
+                        thread.maps = trace.maps.clone();
+                        thread
+                    })
+                    .collect();
+                if let Some(ref reference_threads) = reference_helpers {
+                    if reference_threads.len() != threads.len() {
+                        println!("Run {} started {} threads, run 0 started {}", run, threads.len(),
+                                 reference_threads.len());
+                        same = false;
+                    } else {
+                        for (i, (a, b)) in reference_threads.iter().zip(threads.iter()).enumerate() {
+                            if let Some(divergence) = report::compare(a, b, config) {
+                                if session.symbols.is_none() {
+                                    session.symbols = Some(Symbolizer::new(pid));
+                                }
+                                println!("Thread {} started by run {} differs from the one started by run 0", i, run);
+                                report::print_divergence(a, "run 0", b, &format!("run {}", run), &divergence,
+                                                         session.symbols.as_ref().unwrap());
+                                same = false;
+                            }
+                        }
+                    }
+                } else {
+                    reference_helpers = Some(threads);
+                }
+                run_thread = None;
+
+                run += 1;
+                if reference.is_none() {
+                    reference = Some(trace);
+                }
+                trace = Trace::new();
+                if run == session.total_runs {
+                    break;
+                }
+                resume(tracee, false)?;
+            }
+        }
+    }
+
+    report::print_summary(run, &clusters);
+    Ok(same)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inputs::InputClass;
+
+    // Calls itself through `bounce`, so the inner calls return to the same
+    // address as the outermost one, which `run` also makes through `bounce`.
+    #[inline(never)]
+    fn recurse(n: u64) -> u64 {
+        if n == 0 {
+            1
+        } else {
+            black_box(bounce(n - 1)) + 3
+        }
+    }
+
+    #[inline(never)]
+    fn bounce(n: u64) -> u64 {
+        recurse(black_box(n)) * 2 + 1
+    }
+
+    struct Recursive;
+
+    impl ConstantTimeTarget for Recursive {
+        fn name(&self) -> &str { "recursive" }
+
+        fn generate_inputs(&mut self, _rng: &mut StdRng) -> Vec<Vec<u8>> {
+            vec![vec![4]]
+        }
+
+        fn input_classes(&self) -> Vec<InputClass> {
+            vec![InputClass::Random]
+        }
+
+        fn traced_function(&self) -> Option<&str> {
+            Some("fixed_time_tester::tracer::tests::recurse")
+        }
+
+        fn run(&mut self, inputs: &[Vec<u8>]) {
+            let mut expected = 1;
+            for _ in 0..inputs[0][0] {
+                expected = (expected * 2 + 1) + 3;
+            }
+            // A return breakpoint that was stepped into would have skipped part
+            // of an instruction; crash so the tracer notices.
+            if bounce(inputs[0][0] as u64) != expected * 2 + 1 {
+                std::process::abort();
+            }
+        }
+    }
+
+    fn regs() -> libc::user_regs_struct {
+        let mut regs = ptrace::zeroed_regs();
+        regs.rsp = 0x1000;
+        regs.rbp = 0x2000;
+        regs.rsi = 0x3000;
+        regs.rdi = 0x4000;
+        regs.rax = 0x5000;
+        regs.rcx = 3;
+        regs
+    }
+
+    fn implicit(regs: &libc::user_regs_struct, code: &[u8]) -> Vec<MemAccess> {
+        let instruction = decoder::decode(0, code).unwrap();
+        let mut accesses = Vec::new();
+        assert!(find_implicit_mem_access(regs, &instruction, &mut accesses), "{:02x?}", code);
+        accesses
+    }
+
+    fn load(addr: u64, size: u16) -> MemAccess {
+        MemAccess { addr, size, kind: AccessKind::Load }
+    }
+
+    fn store(addr: u64, size: u16) -> MemAccess {
+        MemAccess { addr, size, kind: AccessKind::Store }
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let regs = regs();
+        assert_eq!(implicit(&regs, &[0x50]), vec![store(0xff8, 8)]); // PUSH RAX
+        assert_eq!(implicit(&regs, &[0x66, 0x50]), vec![store(0xffe, 2)]); // PUSH AX
+        assert_eq!(implicit(&regs, &[0x6a, 0x01]), vec![store(0xff8, 8)]); // PUSH 1
+        assert_eq!(implicit(&regs, &[0x66, 0x6a, 0x01]), vec![store(0xffe, 2)]); // PUSH WORD 1
+        assert_eq!(implicit(&regs, &[0x0f, 0xa0]), vec![store(0xff8, 8)]); // PUSH FS
+        assert_eq!(implicit(&regs, &[0xff, 0x30]), vec![load(0x5000, 8), store(0xff8, 8)]); // PUSH [RAX]
+        assert_eq!(implicit(&regs, &[0x9c]), vec![store(0xff8, 8)]); // PUSHFQ
+        assert_eq!(implicit(&regs, &[0x66, 0x9c]), vec![store(0xffe, 2)]); // PUSHF
+
+        assert_eq!(implicit(&regs, &[0x58]), vec![load(0x1000, 8)]); // POP RAX
+        assert_eq!(implicit(&regs, &[0x66, 0x58]), vec![load(0x1000, 2)]); // POP AX
+        // POP [RSP] stores to where RSP points after the pop.
+        assert_eq!(implicit(&regs, &[0x8f, 0x04, 0x24]), vec![load(0x1000, 8), store(0x1008, 8)]);
+        assert_eq!(implicit(&regs, &[0x66, 0x8f, 0x04, 0x24]), vec![load(0x1000, 2), store(0x1002, 2)]);
+        assert_eq!(implicit(&regs, &[0x9d]), vec![load(0x1000, 8)]); // POPFQ
+    }
+
+    #[test]
+    fn calls_and_frames() {
+        let regs = regs();
+        assert_eq!(implicit(&regs, &[0xe8, 0x00, 0x00, 0x00, 0x00]), vec![store(0xff8, 8)]); // CALL rel32
+        assert_eq!(implicit(&regs, &[0xff, 0x10]), vec![load(0x5000, 8), store(0xff8, 8)]); // CALL [RAX]
+        assert_eq!(implicit(&regs, &[0xc3]), vec![load(0x1000, 8)]); // RET
+        assert_eq!(implicit(&regs, &[0xc9]), vec![load(0x2000, 8)]); // LEAVE
+        assert_eq!(implicit(&regs, &[0xc8, 0x10, 0x00, 0x00]), vec![store(0xff8, 8)]); // ENTER 0x10, 0
+        // ENTER 0x10, 2 copies one frame pointer from the old frame and pushes the
+        // new one.
+        assert_eq!(implicit(&regs, &[0xc8, 0x10, 0x00, 0x02]),
+                   vec![store(0xff8, 8), load(0x1ff8, 8), store(0xff0, 8), store(0xfe8, 8)]);
+    }
+
+    #[test]
+    fn string_instructions() {
+        let mut regs = regs();
+        assert_eq!(implicit(&regs, &[0xf3, 0x48, 0xab]), vec![store(0x4000, 8)]); // REP STOSQ
+        assert_eq!(implicit(&regs, &[0xf3, 0xa4]), vec![load(0x3000, 1), store(0x4000, 1)]); // REP MOVSB
+        assert_eq!(implicit(&regs, &[0xac]), vec![load(0x3000, 1)]); // LODSB
+        assert_eq!(implicit(&regs, &[0xf3, 0x66, 0xa7]), vec![load(0x3000, 2), load(0x4000, 2)]); // REPE CMPSW
+        assert_eq!(implicit(&regs, &[0xf2, 0xae]), vec![load(0x4000, 1)]); // REPNE SCASB
+        // FS and GS overrides apply to RSI but not to RDI.
+        regs.fs_base = 0x10000;
+        assert_eq!(implicit(&regs, &[0x64, 0xa4]), vec![load(0x13000, 1), store(0x4000, 1)]); // MOVSB FS:[RSI]
+        assert_eq!(implicit(&regs, &[0x64, 0xac]), vec![load(0x13000, 1)]); // LODSB FS:[RSI]
+        // A REP iteration with nothing left to do doesn't touch memory.
+        regs.rcx = 0;
+        assert_eq!(implicit(&regs, &[0xf3, 0xa4]), vec![]);
+        assert_eq!(implicit(&regs, &[0xa4]), vec![load(0x3000, 1), store(0x4000, 1)]); // MOVSB
+    }
+
+    #[test]
+    fn recursion_through_the_return_address() {
+        let config = Config { iterations: 2, ..Config::default() };
+        assert!(trace_target(&mut Recursive, &config));
+    }
+}